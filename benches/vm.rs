@@ -0,0 +1,138 @@
+// benches/vm.rs - FalconCore VM benchmarks on loop-heavy scripts
+//
+// Each script is parsed and compiled once; an iteration builds a fresh VM and runs it, so the
+// numbers cover verification and assembly as well as execution. The `enum_dispatch` group is the
+// baseline: the same compiled code run by matching on cloned `Opcode`s, as the VM did before it
+// assembled a byte stream. Measured on one machine, the two are close (vm/while_globals 24.0 ms,
+// enum_dispatch/while_globals 23.2 ms): the gain over the name-keyed VM came from global slots.
+use criterion::{criterion_group, criterion_main, Criterion};
+use falconcore::compiler::{Compiler, Opcode, Program};
+use falconcore::lexer::Lexer;
+use falconcore::parser::Parser;
+use falconcore::vm::{Value, VM};
+use std::hint::black_box;
+
+const SCRIPTS: &[(&str, &str)] = &[
+    (
+        "for_range_sum",
+        "let total = 0
+for i in 0..100000 {
+    total += i
+}
+return total",
+    ),
+    (
+        "while_globals",
+        "let i = 0
+let acc = 0
+while i < 100000 {
+    acc = acc + i % 7
+    i += 1
+}
+return acc",
+    ),
+    (
+        "locals_in_function",
+        "fn run(n) {
+    let acc = 0
+    let i = 0
+    while i < n {
+        acc = acc + i * 2 - 1
+        i += 1
+    }
+    return acc
+}
+return run(100000)",
+    ),
+    (
+        "recursive_fib",
+        "fn fib(n) {
+    if n < 2 { return n }
+    return fib(n - 1) + fib(n - 2)
+}
+return fib(20)",
+    ),
+    (
+        "list_build_and_iterate",
+        "let items = []
+for i in 0..20000 {
+    items.push(i)
+}
+let sum = 0
+for item in items {
+    sum += item
+}
+return sum",
+    ),
+];
+
+fn compile(source: &str) -> Program {
+    let mut parser = Parser::new(Lexer::new(source));
+    let ast = parser.parse().expect("benchmark scripts parse");
+    let mut compiler = Compiler::new();
+    compiler.compile(ast);
+    compiler.program()
+}
+
+fn bench_scripts(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vm");
+    for (name, source) in SCRIPTS {
+        let program = compile(source);
+        group.bench_function(*name, |b| {
+            b.iter(|| VM::new(black_box(program.clone())).run().expect("benchmark scripts run"))
+        });
+    }
+    group.finish();
+}
+
+/// Reference loop over the IR for the integer and global-slot instructions the baseline scripts
+/// use; anything else is a bug in the benchmark.
+fn run_enum_dispatch(program: &Program) -> Value {
+    let mut stack: Vec<Value> = vec![];
+    let mut globals: Vec<Value> = vec![Value::Nil; program.globals.len()];
+    let mut ip = 0;
+    loop {
+        let instruction = program.code[ip].clone();
+        ip += 1;
+        match instruction {
+            Opcode::LoadConst(idx) => stack.push(program.constants[idx].clone()),
+            Opcode::LoadGlobal(slot) => stack.push(globals[slot].clone()),
+            Opcode::StoreGlobal(slot) => globals[slot] = stack.pop().expect("operand"),
+            Opcode::Pop => {
+                stack.pop();
+            }
+            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Mod | Opcode::Less => {
+                let (Some(Value::Int(b)), Some(Value::Int(a))) = (stack.pop(), stack.pop()) else {
+                    panic!("baseline scripts only do integer arithmetic");
+                };
+                stack.push(match instruction {
+                    Opcode::Add => Value::Int(a + b),
+                    Opcode::Sub => Value::Int(a - b),
+                    Opcode::Mul => Value::Int(a * b),
+                    Opcode::Mod => Value::Int(a % b),
+                    _ => Value::Bool(a < b),
+                });
+            }
+            Opcode::JumpIfFalse(target) => {
+                if let Some(Value::Bool(false)) = stack.pop() {
+                    ip = target;
+                }
+            }
+            Opcode::Jump(target) => ip = target,
+            Opcode::Return => return stack.pop().unwrap_or(Value::Nil),
+            other => panic!("{:?} isn't supported by the baseline loop", other),
+        }
+    }
+}
+
+fn bench_baseline(c: &mut Criterion) {
+    let mut group = c.benchmark_group("enum_dispatch");
+    for (name, source) in SCRIPTS.iter().filter(|(name, _)| *name == "while_globals") {
+        let program = compile(source);
+        group.bench_function(*name, |b| b.iter(|| run_enum_dispatch(black_box(&program))));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_scripts, bench_baseline);
+criterion_main!(benches);
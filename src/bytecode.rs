@@ -0,0 +1,650 @@
+// src/bytecode.rs - FalconCore bytecode files (.fbc): versioned binary container for compiled scripts
+//
+// Layout, all integers little-endian:
+//
+//   header     magic "FBC\0", version u16, flags u16 (bit 0: debug section present)
+//   sections   id u8, byte length u32, payload; in this order:
+//     1 constants   count u32, then per constant a tag u8 and its payload
+//     2 globals     count u32 names, as u32 length + UTF-8, in slot order
+//     3 functions   script local count u32, count u32, then arity, local count, start ip,
+//                   capture count (u32s) and captures
+//     4 code        count u32, then per instruction an opcode u8 and its u32 operands
+//     5 lines       count u32 runs of (first ip u32, line u32)
+//     6 debug       optional: count u32 function names, as u32 length + UTF-8
+//
+// Opcode bytes are the VM's (see `chunk::op`), but jump targets count instructions rather than
+// bytes, and member and method names are string constants referenced by index.
+use crate::chunk::{self, op};
+use crate::compiler::{Opcode, Program};
+use crate::value::{Capture, Function, Value};
+use std::fmt;
+use std::rc::Rc;
+
+pub const MAGIC: [u8; 4] = *b"FBC\0";
+pub const FORMAT_VERSION: u16 = 3;
+
+const FLAG_DEBUG: u16 = 1;
+
+// Most local slots a function or the script may ask for. The VM allocates them on every call, so
+// an absurd count in a damaged file would abort on allocation instead of failing to load
+const MAX_LOCALS: usize = 1 << 16;
+
+const SECTION_CONSTANTS: u8 = 1;
+const SECTION_GLOBALS: u8 = 2;
+const SECTION_FUNCTIONS: u8 = 3;
+const SECTION_CODE: u8 = 4;
+const SECTION_LINES: u8 = 5;
+const SECTION_DEBUG: u8 = 6;
+
+const CONST_NIL: u8 = 0;
+const CONST_BOOL: u8 = 1;
+const CONST_INT: u8 = 2;
+const CONST_FLOAT: u8 = 3;
+const CONST_STR: u8 = 4;
+const CONST_FUNCTION: u8 = 5;
+
+/// Why a byte buffer isn't a valid .fbc file.
+#[derive(Debug, Clone)]
+pub struct FormatError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid bytecode at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// Serializes a compiled program. Without `debug`, function names are left out and the
+/// decoder makes up `fn#N` names.
+pub fn encode(program: &Program, debug: bool) -> Vec<u8> {
+    // Member and method names become string constants appended to the pool
+    let mut pool = program.constants.clone();
+    let mut code_section = Writer::default();
+    code_section.len(program.code.len());
+    for instruction in &program.code {
+        encode_op(&mut code_section, &mut pool, instruction);
+    }
+
+    let mut global_section = Writer::default();
+    global_section.len(program.globals.len());
+    for name in &program.globals {
+        global_section.string(name);
+    }
+
+    let functions: Vec<&Rc<Function>> = pool
+        .iter()
+        .filter_map(|value| match value {
+            Value::Function(function) => Some(function),
+            _ => None,
+        })
+        .collect();
+
+    let mut constant_section = Writer::default();
+    constant_section.len(pool.len());
+    let mut function_index = 0;
+    for value in &pool {
+        match value {
+            Value::Nil => constant_section.u8(CONST_NIL),
+            Value::Bool(b) => {
+                constant_section.u8(CONST_BOOL);
+                constant_section.u8(*b as u8);
+            }
+            Value::Int(n) => {
+                constant_section.u8(CONST_INT);
+                constant_section.bytes(&n.to_le_bytes());
+            }
+            Value::Float(f) => {
+                constant_section.u8(CONST_FLOAT);
+                constant_section.bytes(&f.to_bits().to_le_bytes());
+            }
+            Value::Str(s) => {
+                constant_section.u8(CONST_STR);
+                constant_section.string(s);
+            }
+            Value::Function(_) => {
+                constant_section.u8(CONST_FUNCTION);
+                constant_section.len(function_index);
+                function_index += 1;
+            }
+            other => unreachable!("the compiler never puts a {} in the constant pool", other.type_name()),
+        }
+    }
+
+    let mut function_section = Writer::default();
+    function_section.len(program.local_count);
+    function_section.len(functions.len());
+    for function in &functions {
+        function_section.len(function.arity);
+        function_section.len(function.local_count);
+        function_section.len(function.start_ip);
+        function_section.len(function.captures.len());
+        for capture in &function.captures {
+            function_section.u8(capture.is_local as u8);
+            function_section.len(capture.index);
+        }
+    }
+
+    // Runs of consecutive instructions on the same line
+    let mut runs = vec![];
+    for (ip, line) in program.lines.iter().enumerate() {
+        if runs.last().is_none_or(|(_, last)| last != line) {
+            runs.push((ip, *line));
+        }
+    }
+    let mut line_section = Writer::default();
+    line_section.len(runs.len());
+    for (ip, line) in runs {
+        line_section.len(ip);
+        line_section.len(line);
+    }
+
+    let mut out = Writer::default();
+    out.bytes(&MAGIC);
+    out.u16(FORMAT_VERSION);
+    out.u16(if debug { FLAG_DEBUG } else { 0 });
+    out.section(SECTION_CONSTANTS, constant_section);
+    out.section(SECTION_GLOBALS, global_section);
+    out.section(SECTION_FUNCTIONS, function_section);
+    out.section(SECTION_CODE, code_section);
+    out.section(SECTION_LINES, line_section);
+    if debug {
+        let mut debug_section = Writer::default();
+        debug_section.len(functions.len());
+        for function in &functions {
+            debug_section.string(&function.name);
+        }
+        out.section(SECTION_DEBUG, debug_section);
+    }
+    out.buf
+}
+
+/// Parses and validates a .fbc file: the container and every table reference in it. The code
+/// itself is checked by the verifier before the VM runs it.
+pub fn decode(bytes: &[u8]) -> Result<Program, FormatError> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(reader.error_at(0, "not a FalconCore bytecode file (bad magic)".to_string()));
+    }
+    let version = reader.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(reader.error_at(
+            4,
+            format!("unsupported format version {} (this build reads version {})", version, FORMAT_VERSION),
+        ));
+    }
+    let flags = reader.u16()?;
+    if flags & !FLAG_DEBUG != 0 {
+        return Err(reader.error_at(6, format!("unknown flags {:#06x}", flags)));
+    }
+
+    let mut section = reader.section(SECTION_CONSTANTS, "constants")?;
+    let mut constants = vec![];
+    let mut function_slots = vec![];
+    for _ in 0..section.len()? {
+        let at = section.pos;
+        let value = match section.u8()? {
+            CONST_NIL => Value::Nil,
+            CONST_BOOL => match section.u8()? {
+                0 => Value::Bool(false),
+                1 => Value::Bool(true),
+                other => return Err(section.error_at(at + 1, format!("invalid bool {}", other))),
+            },
+            CONST_INT => Value::Int(i64::from_le_bytes(section.array()?)),
+            CONST_FLOAT => Value::Float(f64::from_bits(u64::from_le_bytes(section.array()?))),
+            CONST_STR => Value::string(&section.string()?),
+            CONST_FUNCTION => {
+                // Filled in once the function table has been read
+                function_slots.push((constants.len(), section.len()?, section.pos));
+                Value::Nil
+            }
+            tag => return Err(section.error_at(at, format!("unknown constant tag {}", tag))),
+        };
+        constants.push(value);
+    }
+    section.finish()?;
+
+    let mut section = reader.section(SECTION_GLOBALS, "globals")?;
+    let mut globals = vec![];
+    for _ in 0..section.len()? {
+        globals.push(section.string()?);
+    }
+    section.finish()?;
+
+    let mut section = reader.section(SECTION_FUNCTIONS, "functions")?;
+    let local_count = section.len()?;
+    if local_count > MAX_LOCALS {
+        return Err(section.error(format!("script has {} slots, more than the limit of {}", local_count, MAX_LOCALS)));
+    }
+    let mut functions = vec![];
+    for _ in 0..section.len()? {
+        let arity = section.len()?;
+        let local_count = section.len()?;
+        let start_ip = section.len()?;
+        if local_count > MAX_LOCALS {
+            return Err(section.error(format!("function has {} slots, more than the limit of {}", local_count, MAX_LOCALS)));
+        }
+        if arity > local_count {
+            return Err(section.error(format!("function has {} parameters but only {} slots", arity, local_count)));
+        }
+        let mut captures = vec![];
+        for _ in 0..section.len()? {
+            let is_local = match section.u8()? {
+                0 => false,
+                1 => true,
+                other => return Err(section.error(format!("invalid capture kind {}", other))),
+            };
+            captures.push(Capture {
+                is_local,
+                index: section.len()?,
+            });
+        }
+        functions.push((arity, local_count, start_ip, captures));
+    }
+    section.finish()?;
+
+    let mut section = reader.section(SECTION_CODE, "code")?;
+    let count = section.len()?;
+    let mut code = Vec::with_capacity(count.min(section.remaining()));
+    for _ in 0..count {
+        code.push(decode_op(&mut section, &constants)?);
+    }
+    section.finish()?;
+
+    let mut section = reader.section(SECTION_LINES, "lines")?;
+    let mut lines: Vec<usize> = Vec::with_capacity(code.len());
+    for _ in 0..section.len()? {
+        let at = section.pos;
+        let ip = section.len()?;
+        let line = section.len()?;
+        // The first run starts at 0, each later one after the previous, all inside the code
+        let out_of_order = if lines.is_empty() { ip != 0 } else { ip < lines.len() };
+        if out_of_order || ip >= code.len() {
+            return Err(section.error_at(at, format!("line table run starts at instruction {}", ip)));
+        }
+        if let Some(&previous) = lines.last() {
+            lines.resize(ip, previous);
+        }
+        lines.push(line);
+    }
+    if let Some(&last) = lines.last() {
+        lines.resize(code.len(), last);
+    } else if !code.is_empty() {
+        return Err(section.error("line table is empty".to_string()));
+    }
+    section.finish()?;
+
+    let mut names: Vec<String> = (0..functions.len()).map(|i| format!("fn#{}", i)).collect();
+    if flags & FLAG_DEBUG != 0 {
+        let mut section = reader.section(SECTION_DEBUG, "debug")?;
+        let count = section.len()?;
+        if count != functions.len() {
+            return Err(section.error(format!("{} debug names for {} functions", count, functions.len())));
+        }
+        for name in names.iter_mut() {
+            *name = section.string()?;
+        }
+        section.finish()?;
+    }
+    if reader.remaining() > 0 {
+        return Err(reader.error(format!("{} unexpected trailing bytes", reader.remaining())));
+    }
+
+    let functions: Vec<Rc<Function>> = functions
+        .into_iter()
+        .zip(names)
+        .map(|((arity, local_count, start_ip, captures), name)| {
+            Rc::new(Function {
+                name,
+                arity,
+                local_count,
+                captures,
+                start_ip,
+            })
+        })
+        .collect();
+    for (slot, index, at) in function_slots {
+        let function = functions
+            .get(index)
+            .ok_or_else(|| reader.error_at(at, format!("constant refers to function {} of {}", index, functions.len())))?;
+        if function.start_ip >= code.len() {
+            return Err(reader.error_at(at, format!("function {} starts at {}, past the end of the code", index, function.start_ip)));
+        }
+        constants[slot] = Value::Function(function.clone());
+    }
+
+    Ok(Program {
+        constants,
+        code,
+        globals,
+        lines,
+        local_count,
+    })
+}
+
+fn encode_op(out: &mut Writer, pool: &mut Vec<Value>, instruction: &Opcode) {
+    let (opcode, mut operands) = chunk::encode(instruction);
+    if let Opcode::GetMember(name) | Opcode::Invoke(name, _) = instruction {
+        operands[0] = match pool.iter().position(|value| matches!(value, Value::Str(s) if **s == **name)) {
+            Some(idx) => idx,
+            None => {
+                pool.push(Value::string(name));
+                pool.len() - 1
+            }
+        };
+    }
+    out.u8(opcode);
+    for operand in &operands[..chunk::operand_count(opcode)] {
+        out.len(*operand);
+    }
+}
+
+fn decode_op(input: &mut Reader, constants: &[Value]) -> Result<Opcode, FormatError> {
+    let at = input.pos;
+    let instruction = match input.u8()? {
+        op::LOAD_CONST => Opcode::LoadConst(input.len()?),
+        op::LOAD_GLOBAL => Opcode::LoadGlobal(input.len()?),
+        op::STORE_GLOBAL => Opcode::StoreGlobal(input.len()?),
+        op::LOAD_LOCAL => Opcode::LoadLocal(input.len()?),
+        op::STORE_LOCAL => Opcode::StoreLocal(input.len()?),
+        op::LOAD_UPVALUE => Opcode::LoadUpvalue(input.len()?),
+        op::STORE_UPVALUE => Opcode::StoreUpvalue(input.len()?),
+        op::CLOSE_UPVALUES => Opcode::CloseUpvalues(input.len()?),
+        op::POP => Opcode::Pop,
+        op::ADD => Opcode::Add,
+        op::SUB => Opcode::Sub,
+        op::MUL => Opcode::Mul,
+        op::DIV => Opcode::Div,
+        op::MOD => Opcode::Mod,
+        op::NEGATE => Opcode::Negate,
+        op::EQUAL => Opcode::Equal,
+        op::NOT_EQUAL => Opcode::NotEqual,
+        op::GREATER => Opcode::Greater,
+        op::LESS => Opcode::Less,
+        op::GREATER_EQUAL => Opcode::GreaterEqual,
+        op::LESS_EQUAL => Opcode::LessEqual,
+        op::NOT => Opcode::Not,
+        op::GET_MEMBER => Opcode::GetMember(input.name(constants)?),
+        op::INVOKE => Opcode::Invoke(input.name(constants)?, input.len()?),
+        op::STRINGIFY => Opcode::Stringify,
+        op::BUILD_LIST => Opcode::BuildList(input.len()?),
+        op::BUILD_MAP => Opcode::BuildMap(input.len()?),
+        op::GET_INDEX => Opcode::GetIndex,
+        op::SET_INDEX => Opcode::SetIndex,
+        op::DUP => Opcode::Dup,
+        op::DUP2 => Opcode::Dup2,
+        op::MAKE_SECRET => Opcode::MakeSecret,
+        op::PRINT => Opcode::Print,
+        op::JUMP_IF_FALSE => Opcode::JumpIfFalse(input.len()?),
+        op::JUMP => Opcode::Jump(input.len()?),
+        op::FOR_RANGE => Opcode::ForRange(input.len()?, input.len()?),
+        op::FOR_ITER => Opcode::ForIter(input.len()?, input.len()?),
+        op::PUSH_HANDLER => Opcode::PushHandler(input.len()?, input.len()?),
+        op::POP_HANDLER => Opcode::PopHandler,
+        op::THROW => Opcode::Throw,
+        op::MAKE_FUNCTION => Opcode::MakeFunction(input.len()?),
+        op::CALL => Opcode::Call(input.len()?),
+        op::RETURN => Opcode::Return,
+        tag => return Err(input.error_at(at, format!("unknown opcode {}", tag))),
+    };
+    Ok(instruction)
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, n: u8) {
+        self.buf.push(n);
+    }
+
+    fn u16(&mut self, n: u16) {
+        self.buf.extend_from_slice(&n.to_le_bytes());
+    }
+
+    /// A count, index or line; stored as u32.
+    fn len(&mut self, n: usize) {
+        let n = u32::try_from(n).expect("bytecode counts and indices fit in 32 bits");
+        self.buf.extend_from_slice(&n.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.len(s.len());
+        self.bytes(s.as_bytes());
+    }
+
+    fn section(&mut self, id: u8, section: Writer) {
+        self.u8(id);
+        self.len(section.buf.len());
+        self.bytes(&section.buf);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    // Offset into `bytes`; sections are sub-readers, so errors report the offset in the file
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: String) -> FormatError {
+        self.error_at(self.pos, message)
+    }
+
+    fn error_at(&self, offset: usize, message: String) -> FormatError {
+        FormatError { offset, message }
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], FormatError> {
+        if n > self.remaining() {
+            return Err(self.error(format!("unexpected end of data (wanted {} more bytes)", n)));
+        }
+        let taken = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], FormatError> {
+        Ok(self.take(N)?.try_into().expect("take returned N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FormatError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> Result<usize, FormatError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, FormatError> {
+        let len = self.len()?;
+        let at = self.pos;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.error_at(at, "string is not valid UTF-8".to_string()))
+    }
+
+    /// An instruction operand naming a member or method: a string constant.
+    fn name(&mut self, constants: &[Value]) -> Result<String, FormatError> {
+        let at = self.pos;
+        let idx = self.len()?;
+        match constants.get(idx) {
+            Some(Value::Str(s)) => Ok(s.to_string()),
+            _ => Err(self.error_at(at, format!("name operand {} is not a string constant", idx))),
+        }
+    }
+
+    /// The next section, which must have the given id; the returned reader covers just its payload.
+    fn section(&mut self, id: u8, what: &str) -> Result<Reader<'a>, FormatError> {
+        let at = self.pos;
+        let found = self.u8()?;
+        if found != id {
+            return Err(self.error_at(at, format!("expected the {} section (id {}), found id {}", what, id, found)));
+        }
+        let len = self.len()?;
+        let start = self.pos;
+        self.take(len)?;
+        Ok(Reader {
+            bytes: &self.bytes[..start + len],
+            pos: start,
+        })
+    }
+
+    /// Errors if a section's payload wasn't used up exactly.
+    fn finish(self) -> Result<(), FormatError> {
+        if self.remaining() > 0 {
+            return Err(self.error(format!("{} unexpected bytes at the end of the section", self.remaining())));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::vm::VM;
+    use std::rc::Rc;
+
+    const SCRIPT: &str = "fn counter(start) {
+    let n = start
+    fn next() {
+        n += 1
+        return n
+    }
+    return next
+}
+let next = counter(10)
+next()
+let parts = []
+for word in [\"a\", \"b\"] {
+    parts = parts + [word + \"!\"]
+}
+return [next(), parts, 2.5, 1 == 1.0, { \"k\": 1 }]";
+
+    fn compile(source: &str) -> Program {
+        let ast = Parser::new(Lexer::new(source)).parse().expect("test scripts parse");
+        let mut compiler = Compiler::new();
+        compiler.compile(ast);
+        compiler.program()
+    }
+
+    fn run(program: Program) -> Value {
+        VM::new(program).run().expect("test scripts run")
+    }
+
+    #[test]
+    fn round_trip_keeps_the_program() {
+        let program = compile(SCRIPT);
+        for debug in [true, false] {
+            let decoded = decode(&encode(&program, debug)).expect("an encoded program decodes");
+            assert_eq!(format!("{:?}", decoded.code), format!("{:?}", program.code));
+            assert_eq!(decoded.globals, program.globals);
+            assert_eq!(decoded.lines, program.lines);
+            assert_eq!(decoded.local_count, program.local_count);
+            assert_eq!(run(decoded), run(program.clone()));
+        }
+    }
+
+    #[test]
+    fn stripping_leaves_out_function_names() {
+        let program = compile(SCRIPT);
+        let names = |program: &Program| {
+            let mut names: Vec<String> = program
+                .constants
+                .iter()
+                .filter_map(|constant| match constant {
+                    Value::Function(function) => Some(function.name.clone()),
+                    _ => None,
+                })
+                .collect();
+            names.sort();
+            names
+        };
+        let debug = decode(&encode(&program, true)).unwrap();
+        let stripped = decode(&encode(&program, false)).unwrap();
+        assert_eq!(names(&debug), ["counter", "next"]);
+        assert_eq!(names(&stripped), ["fn#0", "fn#1"]);
+    }
+
+    fn rejection(bytes: &[u8]) -> String {
+        decode(bytes).expect_err("malformed bytes are rejected").message
+    }
+
+    #[test]
+    fn rejects_a_bad_header() {
+        let bytes = encode(&compile("return 1"), true);
+        assert!(rejection(b"not bytecode").contains("bad magic"));
+
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(rejection(&newer).contains("unsupported format version"));
+
+        let mut flags = bytes.clone();
+        flags[6] |= 0x80;
+        assert!(rejection(&flags).contains("unknown flags"));
+    }
+
+    #[test]
+    fn rejects_truncated_and_padded_files() {
+        let bytes = encode(&compile(SCRIPT), true);
+        for len in [3, 8, bytes.len() / 2, bytes.len() - 1] {
+            assert!(decode(&bytes[..len]).is_err(), "accepted {} of {} bytes", len, bytes.len());
+        }
+        let mut padded = bytes.clone();
+        padded.push(0);
+        assert!(decode(&padded).is_err());
+    }
+
+    #[test]
+    fn rejects_an_unknown_opcode() {
+        let mut program = compile("return 1");
+        program.code = vec![Opcode::Return];
+        program.lines = vec![1];
+        let mut bytes = encode(&program, true);
+        // The code section: id, byte length, one instruction, then its opcode byte
+        let section = [SECTION_CODE, 5, 0, 0, 0, 1, 0, 0, 0, op::RETURN];
+        let at = bytes.windows(section.len()).position(|window| window == section).expect("a code section");
+        bytes[at + section.len() - 1] = 0xff;
+        assert!(rejection(&bytes).contains("unknown opcode 255"));
+    }
+
+    #[test]
+    fn rejects_absurd_slot_counts() {
+        let mut program = compile("return 1");
+        program.local_count = MAX_LOCALS + 1;
+        assert!(rejection(&encode(&program, true)).contains("script has"));
+
+        let mut program = compile("fn f() { return 1 }\nreturn f()");
+        for constant in &mut program.constants {
+            if let Value::Function(function) = constant {
+                *constant = Value::Function(Rc::new(Function {
+                    name: function.name.clone(),
+                    arity: function.arity,
+                    local_count: 0x7fff_fff0,
+                    captures: function.captures.clone(),
+                    start_ip: function.start_ip,
+                }));
+            }
+        }
+        assert!(rejection(&encode(&program, true)).contains("function has"));
+    }
+}
@@ -0,0 +1,351 @@
+// src/chunk.rs - FalconCore compact instruction stream (what the VM actually executes)
+//
+// Each instruction is one opcode byte followed by its operands as little-endian u32s. Jump
+// targets and function entry points are byte offsets into the stream; member and method names
+// are indices into `Chunk::names`.
+use crate::compiler::{Opcode, Program};
+use crate::value::{Function, Value};
+use std::rc::Rc;
+
+/// Opcode bytes, shared with the .fbc code section.
+pub mod op {
+    pub const LOAD_CONST: u8 = 0;
+    pub const LOAD_GLOBAL: u8 = 1;
+    pub const STORE_GLOBAL: u8 = 2;
+    pub const LOAD_LOCAL: u8 = 3;
+    pub const STORE_LOCAL: u8 = 4;
+    pub const LOAD_UPVALUE: u8 = 5;
+    pub const STORE_UPVALUE: u8 = 6;
+    pub const CLOSE_UPVALUES: u8 = 7;
+    pub const POP: u8 = 8;
+    pub const ADD: u8 = 9;
+    pub const SUB: u8 = 10;
+    pub const MUL: u8 = 11;
+    pub const DIV: u8 = 12;
+    pub const MOD: u8 = 13;
+    pub const NEGATE: u8 = 14;
+    pub const EQUAL: u8 = 15;
+    pub const NOT_EQUAL: u8 = 16;
+    pub const GREATER: u8 = 17;
+    pub const LESS: u8 = 18;
+    pub const GREATER_EQUAL: u8 = 19;
+    pub const LESS_EQUAL: u8 = 20;
+    pub const NOT: u8 = 21;
+    pub const GET_MEMBER: u8 = 22;
+    pub const INVOKE: u8 = 23;
+    pub const STRINGIFY: u8 = 24;
+    pub const BUILD_LIST: u8 = 25;
+    pub const BUILD_MAP: u8 = 26;
+    pub const GET_INDEX: u8 = 27;
+    pub const SET_INDEX: u8 = 28;
+    pub const DUP: u8 = 29;
+    pub const DUP2: u8 = 30;
+    pub const MAKE_SECRET: u8 = 31;
+    pub const PRINT: u8 = 32;
+    pub const JUMP_IF_FALSE: u8 = 33;
+    pub const JUMP: u8 = 34;
+    pub const FOR_RANGE: u8 = 35;
+    pub const FOR_ITER: u8 = 36;
+    pub const PUSH_HANDLER: u8 = 37;
+    pub const POP_HANDLER: u8 = 38;
+    pub const THROW: u8 = 39;
+    pub const MAKE_FUNCTION: u8 = 40;
+    pub const CALL: u8 = 41;
+    pub const RETURN: u8 = 42;
+}
+
+/// Size in bytes of an instruction, indexed by its opcode byte.
+pub const WIDTH: [usize; 256] = {
+    let mut width = [1; 256];
+    let mut op = 0;
+    while op < 256 {
+        width[op] = 1 + 4 * operand_count(op as u8);
+        op += 1;
+    }
+    width
+};
+
+/// How many u32 operands follow an opcode byte.
+pub const fn operand_count(code: u8) -> usize {
+    match code {
+        op::INVOKE | op::FOR_RANGE | op::FOR_ITER | op::PUSH_HANDLER => 2,
+        op::LOAD_CONST
+        | op::LOAD_GLOBAL
+        | op::STORE_GLOBAL
+        | op::LOAD_LOCAL
+        | op::STORE_LOCAL
+        | op::LOAD_UPVALUE
+        | op::STORE_UPVALUE
+        | op::CLOSE_UPVALUES
+        | op::GET_MEMBER
+        | op::BUILD_LIST
+        | op::BUILD_MAP
+        | op::JUMP_IF_FALSE
+        | op::JUMP
+        | op::MAKE_FUNCTION
+        | op::CALL => 1,
+        _ => 0,
+    }
+}
+
+/// The opcode byte for an instruction, and its operands as they appear in the IR.
+pub fn encode(instruction: &Opcode) -> (u8, [usize; 2]) {
+    match *instruction {
+        Opcode::LoadConst(idx) => (op::LOAD_CONST, [idx, 0]),
+        Opcode::LoadGlobal(slot) => (op::LOAD_GLOBAL, [slot, 0]),
+        Opcode::StoreGlobal(slot) => (op::STORE_GLOBAL, [slot, 0]),
+        Opcode::LoadLocal(slot) => (op::LOAD_LOCAL, [slot, 0]),
+        Opcode::StoreLocal(slot) => (op::STORE_LOCAL, [slot, 0]),
+        Opcode::LoadUpvalue(index) => (op::LOAD_UPVALUE, [index, 0]),
+        Opcode::StoreUpvalue(index) => (op::STORE_UPVALUE, [index, 0]),
+        Opcode::CloseUpvalues(slot) => (op::CLOSE_UPVALUES, [slot, 0]),
+        Opcode::Pop => (op::POP, [0, 0]),
+        Opcode::Add => (op::ADD, [0, 0]),
+        Opcode::Sub => (op::SUB, [0, 0]),
+        Opcode::Mul => (op::MUL, [0, 0]),
+        Opcode::Div => (op::DIV, [0, 0]),
+        Opcode::Mod => (op::MOD, [0, 0]),
+        Opcode::Negate => (op::NEGATE, [0, 0]),
+        Opcode::Equal => (op::EQUAL, [0, 0]),
+        Opcode::NotEqual => (op::NOT_EQUAL, [0, 0]),
+        Opcode::Greater => (op::GREATER, [0, 0]),
+        Opcode::Less => (op::LESS, [0, 0]),
+        Opcode::GreaterEqual => (op::GREATER_EQUAL, [0, 0]),
+        Opcode::LessEqual => (op::LESS_EQUAL, [0, 0]),
+        Opcode::Not => (op::NOT, [0, 0]),
+        // Names are left for the caller to turn into an index
+        Opcode::GetMember(_) => (op::GET_MEMBER, [0, 0]),
+        Opcode::Invoke(_, arg_count) => (op::INVOKE, [0, arg_count]),
+        Opcode::Stringify => (op::STRINGIFY, [0, 0]),
+        Opcode::BuildList(count) => (op::BUILD_LIST, [count, 0]),
+        Opcode::BuildMap(count) => (op::BUILD_MAP, [count, 0]),
+        Opcode::GetIndex => (op::GET_INDEX, [0, 0]),
+        Opcode::SetIndex => (op::SET_INDEX, [0, 0]),
+        Opcode::Dup => (op::DUP, [0, 0]),
+        Opcode::Dup2 => (op::DUP2, [0, 0]),
+        Opcode::MakeSecret => (op::MAKE_SECRET, [0, 0]),
+        Opcode::Print => (op::PRINT, [0, 0]),
+        Opcode::JumpIfFalse(target) => (op::JUMP_IF_FALSE, [target, 0]),
+        Opcode::Jump(target) => (op::JUMP, [target, 0]),
+        Opcode::ForRange(slot, exit) => (op::FOR_RANGE, [slot, exit]),
+        Opcode::ForIter(slot, exit) => (op::FOR_ITER, [slot, exit]),
+        Opcode::PushHandler(catch_ip, slot) => (op::PUSH_HANDLER, [catch_ip, slot]),
+        Opcode::PopHandler => (op::POP_HANDLER, [0, 0]),
+        Opcode::Throw => (op::THROW, [0, 0]),
+        Opcode::MakeFunction(idx) => (op::MAKE_FUNCTION, [idx, 0]),
+        Opcode::Call(arg_count) => (op::CALL, [arg_count, 0]),
+        Opcode::Return => (op::RETURN, [0, 0]),
+    }
+}
+
+/// Which operand of an instruction is a jump target, if any.
+fn target_operand(code: u8) -> Option<usize> {
+    match code {
+        op::JUMP | op::JUMP_IF_FALSE | op::PUSH_HANDLER => Some(0),
+        op::FOR_RANGE | op::FOR_ITER => Some(1),
+        _ => None,
+    }
+}
+
+/// A program lowered for execution.
+#[derive(Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    // The program's constants, with function entry points turned into byte offsets
+    pub constants: Vec<Value>,
+    pub names: Vec<String>,
+    // Runs of (first byte offset, source line)
+    pub lines: Vec<(usize, usize)>,
+}
+
+impl Chunk {
+    /// Lowers a verified program; unchecked operands would index out of bounds here.
+    pub fn assemble(program: &Program) -> Chunk {
+        let mut code = Vec::with_capacity(program.code.len() * 5);
+        let mut names: Vec<String> = vec![];
+        let mut lines: Vec<(usize, usize)> = vec![];
+        // Byte offset of every instruction, and one past the end for jumps to the end
+        let mut offsets = Vec::with_capacity(program.code.len() + 1);
+        let mut jumps = vec![];
+
+        for (ip, instruction) in program.code.iter().enumerate() {
+            offsets.push(code.len());
+            if let Some(&line) = program.lines.get(ip) {
+                if lines.last().is_none_or(|(_, last)| *last != line) {
+                    lines.push((code.len(), line));
+                }
+            }
+
+            let (opcode, mut operands) = encode(instruction);
+            if let Opcode::GetMember(name) | Opcode::Invoke(name, _) = instruction {
+                operands[0] = match names.iter().position(|known| known == name) {
+                    Some(idx) => idx,
+                    None => {
+                        names.push(name.clone());
+                        names.len() - 1
+                    }
+                };
+            }
+            if let Some(operand) = target_operand(opcode) {
+                jumps.push(code.len() + 1 + 4 * operand);
+            }
+            code.push(opcode);
+            for operand in &operands[..operand_count(opcode)] {
+                let operand = u32::try_from(*operand).expect("operands fit in 32 bits");
+                code.extend_from_slice(&operand.to_le_bytes());
+            }
+        }
+        offsets.push(code.len());
+
+        // Targets were instruction indices until every offset was known
+        for at in jumps {
+            let target = u32::from_le_bytes(code[at..at + 4].try_into().expect("4 bytes")) as usize;
+            code[at..at + 4].copy_from_slice(&(offsets[target] as u32).to_le_bytes());
+        }
+
+        let constants = program
+            .constants
+            .iter()
+            .map(|value| match value {
+                Value::Function(function) => Value::Function(Rc::new(Function {
+                    name: function.name.clone(),
+                    arity: function.arity,
+                    local_count: function.local_count,
+                    captures: function.captures.clone(),
+                    start_ip: offsets[function.start_ip],
+                })),
+                other => other.clone(),
+            })
+            .collect();
+
+        Chunk {
+            code,
+            constants,
+            names,
+            lines,
+        }
+    }
+
+    /// The source line of the instruction at byte `offset`, or 0 without line information.
+    pub fn line_at(&self, offset: usize) -> usize {
+        match self.lines.partition_point(|(start, _)| *start <= offset) {
+            0 => 0,
+            run => self.lines[run - 1].1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::compiler::Compiler;
+
+    const SCRIPT: &str = "fn make(step) {
+    let total = 0
+    fn add() {
+        total += step
+        return total
+    }
+    return add
+}
+let add = make(2)
+let words = []
+for i in 0..3 {
+    if i == 1 {
+        continue
+    }
+    try {
+        words.push(\"w\" + i)
+    } catch e {
+        print e.message
+    }
+}
+while words.length() < 4 {
+    words.push(add())
+}
+return words";
+
+    fn compile(source: &str) -> Program {
+        let ast = Parser::new(Lexer::new(source)).parse().expect("test scripts parse");
+        let mut compiler = Compiler::new();
+        compiler.compile(ast);
+        compiler.program()
+    }
+
+    /// Each instruction as its opcode byte and operands, with jump targets as instruction indices
+    /// and names as indices into `names`.
+    fn expected(program: &Program, names: &[String]) -> Vec<(u8, Vec<usize>)> {
+        program
+            .code
+            .iter()
+            .map(|instruction| {
+                let (opcode, mut operands) = encode(instruction);
+                if let Opcode::GetMember(name) | Opcode::Invoke(name, _) = instruction {
+                    operands[0] = names.iter().position(|known| known == name).expect("name is interned");
+                }
+                (opcode, operands[..operand_count(opcode)].to_vec())
+            })
+            .collect()
+    }
+
+    /// Reads the byte stream back the same way, turning byte offsets into instruction indices.
+    fn disassemble(chunk: &Chunk) -> (Vec<usize>, Vec<(u8, Vec<usize>)>) {
+        let mut offsets = vec![];
+        let mut at = 0;
+        while at < chunk.code.len() {
+            offsets.push(at);
+            at += WIDTH[chunk.code[at] as usize];
+        }
+        assert_eq!(at, chunk.code.len(), "the last instruction runs past the end");
+
+        let instructions = offsets
+            .iter()
+            .map(|&at| {
+                let opcode = chunk.code[at];
+                let mut operands: Vec<usize> = (0..operand_count(opcode))
+                    .map(|n| u32::from_le_bytes(chunk.code[at + 1 + 4 * n..at + 5 + 4 * n].try_into().unwrap()) as usize)
+                    .collect();
+                if let Some(n) = target_operand(opcode) {
+                    operands[n] = offsets.iter().position(|&offset| offset == operands[n]).unwrap_or(offsets.len());
+                }
+                (opcode, operands)
+            })
+            .collect();
+        (offsets, instructions)
+    }
+
+    #[test]
+    fn assembly_round_trips_every_instruction() {
+        let program = compile(SCRIPT);
+        let chunk = Chunk::assemble(&program);
+        let (offsets, instructions) = disassemble(&chunk);
+        assert_eq!(instructions, expected(&program, &chunk.names));
+
+        // Names are interned once each
+        let mut names = chunk.names.clone();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), chunk.names.len());
+
+        for (ip, &offset) in offsets.iter().enumerate() {
+            assert_eq!(chunk.line_at(offset), program.lines[ip], "line of instruction {}", ip);
+        }
+    }
+
+    #[test]
+    fn function_entry_points_become_byte_offsets() {
+        let program = compile(SCRIPT);
+        let chunk = Chunk::assemble(&program);
+        let (offsets, _) = disassemble(&chunk);
+        let mut functions = 0;
+        for (before, after) in program.constants.iter().zip(&chunk.constants) {
+            if let (Value::Function(before), Value::Function(after)) = (before, after) {
+                assert_eq!(after.start_ip, offsets[before.start_ip], "{}", before.name);
+                functions += 1;
+            }
+        }
+        assert_eq!(functions, 2);
+    }
+}
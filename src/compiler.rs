@@ -1,25 +1,182 @@
 // src/compiler.rs - FalconCore Bytecode Compiler (Updated for VM)
+use crate::lexer::TokenType;
 use crate::parser::Expr;
+use crate::span::{Span, Spanned};
+use crate::value::{Capture, Function, Value};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub enum Opcode {
     LoadConst(usize),
-    LoadVar(String),
-    StoreVar(String),
+    // Globals live in numbered slots; `Program::globals` has their names
+    LoadGlobal(usize),
+    StoreGlobal(usize),
+    LoadLocal(usize),
+    StoreLocal(usize),
+    LoadUpvalue(usize),
+    StoreUpvalue(usize),
+    // Closes every open upvalue pointing at this slot or above
+    CloseUpvalues(usize),
+    Pop,
     Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Negate,
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    GreaterEqual,
+    LessEqual,
+    Not,
+    GetMember(String),
+    // Method call: receiver, then the given number of arguments
+    Invoke(String, usize),
+    // Converts the top of the stack to its display string; secrets stay secret
+    Stringify,
+    BuildList(usize),
+    // Pops that many key/value pairs
+    BuildMap(usize),
+    GetIndex,
+    SetIndex,
+    // Duplicates the top stack value, so `and`/`or` can keep a left operand that decides the result
+    Dup,
+    // Duplicates the top two stack values, for compound index assignment
+    Dup2,
+    MakeSecret,
     Print,
     JumpIfFalse(usize),
     Jump(usize),
-    RepeatStart(usize),
-    RepeatEnd,
-    Call(String, usize),
+    // Counted iteration over two hidden slots (next, end); pushes `next` or jumps to the exit
+    ForRange(usize, usize),
+    // Iteration over a list/string/map in a hidden slot, with its position in the slot after
+    ForIter(usize, usize),
+    // Installs an error handler: a throw jumps to the first operand, closing upvalues from the slot in the second
+    PushHandler(usize, usize),
+    PopHandler,
+    Throw,
+    MakeFunction(usize),
+    Call(usize),
     Return,
 }
 
+struct Local {
+    name: String,
+    depth: usize,
+    captured: bool,
+    // Bound by `secure let/const`; assignments to it stay secret
+    secure: bool,
+}
+
+/// An enclosing loop that `break`/`continue` can jump out of.
+struct Loop {
+    continue_target: usize,
+    // Locals from this slot up belong to the loop body
+    body_slot: usize,
+    break_jumps: Vec<usize>,
+}
+
+/// A `try` whose handler is installed at this point in the code.
+#[derive(Clone)]
+struct Try {
+    // Run on the way out when `return`/`break`/`continue` leaves the protected block
+    finally: Option<Vec<Spanned<Expr>>>,
+    // Loops around the `try`; break/continue only leave the tries inside the innermost loop
+    loop_depth: usize,
+}
+
+/// Bookkeeping for one function being compiled; the top-level script is the outermost.
+struct FunctionScope {
+    // Indexed by slot; block exits truncate this so slots get reused
+    locals: Vec<Local>,
+    captures: Vec<Capture>,
+    loops: Vec<Loop>,
+    tries: Vec<Try>,
+    // Block nesting depth; `let` at depth 0 in the script declares a global
+    depth: usize,
+    slot_count: usize,
+}
+
+impl FunctionScope {
+    fn new(depth: usize) -> Self {
+        FunctionScope {
+            locals: vec![],
+            captures: vec![],
+            loops: vec![],
+            tries: vec![],
+            depth,
+            slot_count: 0,
+        }
+    }
+
+    fn resolve(&self, name: &str) -> Option<usize> {
+        self.locals.iter().rposition(|local| local.name == name)
+    }
+
+    fn declare(&mut self, name: &str) -> usize {
+        self.locals.push(Local {
+            name: name.to_string(),
+            depth: self.depth,
+            captured: false,
+            secure: false,
+        });
+        self.slot_count = self.slot_count.max(self.locals.len());
+        self.locals.len() - 1
+    }
+
+    fn capture(&mut self, is_local: bool, index: usize) -> usize {
+        let capture = Capture { is_local, index };
+        if let Some(existing) = self.captures.iter().position(|c| *c == capture) {
+            return existing;
+        }
+        self.captures.push(capture);
+        self.captures.len() - 1
+    }
+}
+
+/// Where a name lives at the point it is used.
+enum Place {
+    Local(usize),
+    Upvalue(usize),
+    Global,
+}
+
+/// A compiled script: everything the VM, or a .fbc file, needs to run it.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub constants: Vec<Value>,
+    pub code: Vec<Opcode>,
+    // Name of each global slot
+    pub globals: Vec<String>,
+    // Source line of each instruction in `code`
+    pub lines: Vec<usize>,
+    // Slots the script's own block locals need, like a function's `local_count`
+    pub local_count: usize,
+}
+
 pub struct Compiler {
-    constants: Vec<Expr>,
+    constants: Vec<Value>,
     code: Vec<Opcode>,
-    labels: Vec<usize>,
+    // Global names in slot order, and the reverse lookup
+    globals: Vec<String>,
+    global_slots: HashMap<String, usize>,
+    // Source span of each instruction in `code`
+    spans: Vec<Span>,
+    // Span of the innermost node being compiled
+    span: Span,
+    // Innermost last; index 0 is the top-level script
+    scopes: Vec<FunctionScope>,
+    // Global slots currently bound by `secure let/const`; locals keep the flag themselves
+    secure_globals: HashSet<usize>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Compiler {
@@ -27,179 +184,680 @@ impl Compiler {
         Compiler {
             constants: vec![],
             code: vec![],
-            labels: vec![],
+            globals: vec![],
+            global_slots: HashMap::new(),
+            spans: vec![],
+            span: Span::default(),
+            scopes: vec![FunctionScope::new(0)],
+            secure_globals: HashSet::new(),
         }
     }
 
-    pub fn compile(&mut self, ast: Vec<Expr>) {
-        for expr in ast {
-            self.compile_expr(&expr);
+    pub fn compile(&mut self, ast: Vec<Spanned<Expr>>) {
+        for stmt in &ast {
+            self.compile_stmt(stmt);
         }
-        self.code.push(Opcode::Return);
+        self.emit(Opcode::Return);
+    }
+
+    fn emit(&mut self, op: Opcode) {
+        self.code.push(op);
+        self.spans.push(self.span);
+    }
+
+    /// Like `compile_expr`, but a bare expression's value is discarded.
+    fn compile_stmt(&mut self, stmt: &Spanned<Expr>) {
+        self.compile_expr(stmt);
+        if produces_value(&stmt.node) {
+            self.emit(Opcode::Pop);
+        }
+    }
+
+    fn compile_expr(&mut self, expr: &Spanned<Expr>) {
+        // Instructions belong to the innermost node; a parent's own instructions come after its
+        // children, so its span is put back once each child is done
+        let outer = std::mem::replace(&mut self.span, expr.span);
+        self.compile_node(&expr.node);
+        self.span = outer;
     }
 
-    fn compile_expr(&mut self, expr: &Expr) {
+    fn compile_node(&mut self, expr: &Expr) {
         match expr {
             Expr::Number(n) => {
-                let idx = self.add_constant(Expr::Number(*n));
-                self.code.push(Opcode::LoadConst(idx));
+                let idx = self.add_constant(Value::Int(*n));
+                self.emit(Opcode::LoadConst(idx));
+            }
+            Expr::Float(f) => {
+                let idx = self.add_constant(Value::Float(*f));
+                self.emit(Opcode::LoadConst(idx));
+            }
+            Expr::Bool(b) => {
+                let idx = self.add_constant(Value::Bool(*b));
+                self.emit(Opcode::LoadConst(idx));
             }
             Expr::String(s) => {
-                let idx = self.add_constant(Expr::String(s.clone()));
-                self.code.push(Opcode::LoadConst(idx));
+                let idx = self.add_constant(Value::string(s));
+                self.emit(Opcode::LoadConst(idx));
             }
-            Expr::Identifier(id) => {
-                self.code.push(Opcode::LoadVar(id.clone()));
+            Expr::Identifier(name) => self.load(name),
+            // Short-circuits: the left operand is the result when it decides it, else the right one
+            Expr::Binary { left, op: op @ (TokenType::And | TokenType::Or), right } => {
+                self.compile_expr(left);
+                self.emit(Opcode::Dup);
+                if *op == TokenType::Or {
+                    self.emit(Opcode::Not);
+                }
+                let jump_pos = self.code.len();
+                self.emit(Opcode::JumpIfFalse(0)); // placeholder
+                self.emit(Opcode::Pop);
+                self.compile_expr(right);
+                self.code[jump_pos] = Opcode::JumpIfFalse(self.code.len());
             }
             Expr::Binary { left, op, right } => {
                 self.compile_expr(left);
                 self.compile_expr(right);
+                self.emit(binary_opcode(op));
+            }
+            Expr::Unary { op, operand } => {
+                self.compile_expr(operand);
                 match op {
-                    TokenType::Plus => self.code.push(Opcode::Add),
-                    _ => panic!("Unsupported operator"),
+                    TokenType::Minus => self.emit(Opcode::Negate),
+                    TokenType::Bang => self.emit(Opcode::Not),
+                    _ => panic!("Unsupported unary operator"),
+                }
+            }
+            Expr::Call { callee, args } => {
+                if let Expr::Member { object, name } = &callee.node {
+                    self.compile_expr(object);
+                    for arg in args {
+                        self.compile_expr(arg);
+                    }
+                    self.emit(Opcode::Invoke(name.clone(), args.len()));
+                    return;
+                }
+                self.compile_expr(callee);
+                for arg in args {
+                    self.compile_expr(arg);
+                }
+                self.emit(Opcode::Call(args.len()));
+            }
+            Expr::Member { object, name } => {
+                self.compile_expr(object);
+                self.emit(Opcode::GetMember(name.clone()));
+            }
+            Expr::Interpolation(parts) => {
+                for (i, part) in parts.iter().enumerate() {
+                    self.compile_expr(part);
+                    if !matches!(part.node, Expr::String(_)) {
+                        self.emit(Opcode::Stringify);
+                    }
+                    if i > 0 {
+                        self.emit(Opcode::Add);
+                    }
+                }
+            }
+            Expr::List(items) => {
+                for item in items {
+                    self.compile_expr(item);
                 }
+                self.emit(Opcode::BuildList(items.len()));
             }
-            Expr::Let { name, value, .. } => {
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.compile_expr(key);
+                    self.compile_expr(value);
+                }
+                self.emit(Opcode::BuildMap(entries.len()));
+            }
+            Expr::Index { object, index } => {
+                self.compile_expr(object);
+                self.compile_expr(index);
+                self.emit(Opcode::GetIndex);
+            }
+            Expr::SetIndex { object, index, op, value } => {
+                self.compile_expr(object);
+                self.compile_expr(index);
+                if let Some(op) = op {
+                    self.emit(Opcode::Dup2);
+                    self.emit(Opcode::GetIndex);
+                    self.compile_expr(value);
+                    self.emit(binary_opcode(op));
+                } else {
+                    self.compile_expr(value);
+                }
+                self.emit(Opcode::SetIndex);
+            }
+            Expr::Let { is_secure, name, value, .. } => {
+                let name = &name.node;
                 self.compile_expr(value);
-                self.code.push(Opcode::StoreVar(name.clone()));
+                if *is_secure {
+                    self.emit(Opcode::MakeSecret);
+                }
+                // Declared after the initializer, which can't see the new binding
+                self.declare(name);
+                self.mark_secure(name, *is_secure);
+                self.store(name);
+            }
+            Expr::Assign { name, op, value } => {
+                if let Some(op) = op {
+                    self.load(name);
+                    self.compile_expr(value);
+                    self.emit(binary_opcode(op));
+                } else {
+                    self.compile_expr(value);
+                }
+                if self.is_secure(name) {
+                    self.emit(Opcode::MakeSecret);
+                }
+                self.store(name);
             }
             Expr::Print { expr } => {
                 self.compile_expr(expr);
-                self.code.push(Opcode::Print);
+                self.emit(Opcode::Print);
             }
-            Expr::If { condition, then_branch, else_branch } => {
-                self.compile_expr(condition);
-                let jump_false_pos = self.code.len();
-                self.code.push(Opcode::JumpIfFalse(0)); // placeholder
-
-                for stmt in then_branch {
-                    self.compile_expr(stmt);
-                }
+            Expr::If { branches, else_branch } => {
+                // One ladder: each failed test falls through to the next, each taken branch jumps to the end
+                let mut end_jumps = vec![];
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    self.compile_expr(condition);
+                    let jump_false_pos = self.code.len();
+                    self.emit(Opcode::JumpIfFalse(0)); // placeholder
 
-                let jump_end_pos = self.code.len();
-                self.code.push(Opcode::Jump(0)); // placeholder
+                    self.compile_block(body);
 
-                self.code[jump_false_pos] = Opcode::JumpIfFalse(self.code.len());
+                    let is_last = i == branches.len() - 1 && else_branch.is_none();
+                    if !is_last {
+                        end_jumps.push(self.code.len());
+                        self.emit(Opcode::Jump(0)); // placeholder
+                    }
+                    self.code[jump_false_pos] = Opcode::JumpIfFalse(self.code.len());
+                }
 
                 if let Some(else_branch) = else_branch {
-                    for stmt in else_branch {
-                        self.compile_expr(stmt);
-                    }
+                    self.compile_block(else_branch);
                 }
 
-                self.code[jump_end_pos] = Opcode::Jump(self.code.len());
+                for pos in end_jumps {
+                    self.code[pos] = Opcode::Jump(self.code.len());
+                }
             }
             Expr::Repeat { times, body } => {
+                // Same as `for _ in 0..times`
+                self.begin_scope();
+                let zero = self.add_constant(Value::Int(0));
+                self.emit(Opcode::LoadConst(zero));
+                let slot = self.declare_hidden("(repeat next)");
+                self.emit(Opcode::StoreLocal(slot));
                 self.compile_expr(times);
+                self.declare_hidden("(repeat end)");
+                self.emit(Opcode::StoreLocal(slot + 1));
+
                 let loop_start = self.code.len();
-                self.code.push(Opcode::RepeatStart(loop_start));
+                self.emit(Opcode::ForRange(slot, 0)); // placeholder exit
+                self.emit(Opcode::Pop);
+                self.compile_loop_body(loop_start, None, body);
+                self.code[loop_start] = Opcode::ForRange(slot, self.code.len());
+                self.end_scope();
+            }
+            Expr::While { condition, body } => {
+                let loop_start = self.code.len();
+                self.compile_expr(condition);
+                let exit_pos = self.code.len();
+                self.emit(Opcode::JumpIfFalse(0)); // placeholder
+                self.compile_loop_body(loop_start, None, body);
+                self.code[exit_pos] = Opcode::JumpIfFalse(self.code.len());
+            }
+            Expr::ForRange { var, start, end, body } => {
+                self.begin_scope();
+                self.compile_expr(start);
+                let slot = self.declare_hidden("(for next)");
+                self.emit(Opcode::StoreLocal(slot));
+                self.compile_expr(end);
+                self.declare_hidden("(for end)");
+                self.emit(Opcode::StoreLocal(slot + 1));
 
-                for stmt in body {
-                    self.compile_expr(stmt);
-                }
+                let loop_start = self.code.len();
+                self.emit(Opcode::ForRange(slot, 0)); // placeholder exit
+                self.compile_loop_body(loop_start, Some(var), body);
+                self.code[loop_start] = Opcode::ForRange(slot, self.code.len());
+                self.end_scope();
+            }
+            Expr::ForIn { var, iterable, body } => {
+                self.begin_scope();
+                self.compile_expr(iterable);
+                let slot = self.declare_hidden("(for iterable)");
+                self.emit(Opcode::StoreLocal(slot));
+                let zero = self.add_constant(Value::Int(0));
+                self.emit(Opcode::LoadConst(zero));
+                self.declare_hidden("(for position)");
+                self.emit(Opcode::StoreLocal(slot + 1));
 
-                self.code.push(Opcode::RepeatEnd);
+                let loop_start = self.code.len();
+                self.emit(Opcode::ForIter(slot, 0)); // placeholder exit
+                self.compile_loop_body(loop_start, Some(var), body);
+                self.code[loop_start] = Opcode::ForIter(slot, self.code.len());
+                self.end_scope();
+            }
+            Expr::Break | Expr::Continue => {
+                let scope = self.scope();
+                let inner = scope.loops.last().expect("resolver rejects break/continue outside loops");
+                let (body_slot, continue_target) = (inner.body_slot, inner.continue_target);
+                let loop_depth = scope.loops.len();
+                let inside = scope.tries.iter().rev().take_while(|t| t.loop_depth == loop_depth).count();
+                self.exit_tries(inside);
+                // Leaving the body skips the block exit that would close its captured locals
+                if self.scope().locals.len() > body_slot {
+                    self.emit(Opcode::CloseUpvalues(body_slot));
+                }
+                let pos = self.code.len();
+                if *expr == Expr::Break {
+                    self.emit(Opcode::Jump(0)); // patched when the loop ends
+                    self.scope().loops.last_mut().unwrap().break_jumps.push(pos);
+                } else {
+                    self.emit(Opcode::Jump(continue_target));
+                }
             }
-            Expr::FnDef { name, params, body } => {
-                // Store function start position
+            Expr::FnDef { name, params, body, .. } => {
+                // The body is emitted inline and jumped over; calls enter at `start_ip`
+                let jump_over_pos = self.code.len();
+                self.emit(Opcode::Jump(0)); // placeholder
                 let start_ip = self.code.len();
-                self.code.push(Opcode::Return); // placeholder for return
+
+                // Declared first so the body can refer to the function recursively
+                self.declare(name);
+
+                // Parameters occupy the first slots, in order
+                let mut scope = FunctionScope::new(1);
+                for param in params {
+                    scope.declare(param);
+                }
+                self.scopes.push(scope);
                 for stmt in body {
-                    self.compile_expr(stmt);
+                    self.compile_stmt(stmt);
                 }
-                // self.functions.insert(name.clone(), (params.len(), start_ip)); // later
+                // Falling off the end returns nil
+                let nil = self.add_constant(Value::Nil);
+                self.emit(Opcode::LoadConst(nil));
+                self.emit(Opcode::Return);
+                let scope = self.scopes.pop().expect("function scope was pushed above");
+
+                self.code[jump_over_pos] = Opcode::Jump(self.code.len());
+
+                let idx = self.add_constant(Value::Function(Rc::new(Function {
+                    name: name.clone(),
+                    arity: params.len(),
+                    local_count: scope.slot_count,
+                    captures: scope.captures,
+                    start_ip,
+                })));
+                self.emit(Opcode::MakeFunction(idx));
+                self.store(name);
             }
             Expr::Return { value } => {
-                if let Some(val) = value {
-                    self.compile_expr(val);
+                match value {
+                    Some(val) => self.compile_expr(val),
+                    None => {
+                        let nil = self.add_constant(Value::Nil);
+                        self.emit(Opcode::LoadConst(nil));
+                    }
+                }
+                let tries = self.scope().tries.len();
+                self.exit_tries(tries);
+                self.emit(Opcode::Return);
+            }
+            Expr::Throw { value } => {
+                self.compile_expr(value);
+                self.emit(Opcode::Throw);
+            }
+            Expr::Try { body, catch, finally } => {
+                // Upvalues of locals declared inside the protected block are closed when a throw lands here
+                let slot = self.scope().locals.len();
+                let mut handler_pos = self.code.len();
+                self.emit(Opcode::PushHandler(0, slot)); // placeholder
+                self.compile_protected(body, finally);
+                self.emit(Opcode::PopHandler);
+
+                let mut pending = Some(handler_pos);
+                if let Some((var, handler)) = catch {
+                    let skip_pos = self.code.len();
+                    self.emit(Opcode::Jump(0)); // placeholder
+                    self.code[handler_pos] = Opcode::PushHandler(self.code.len(), slot);
+                    pending = None;
+
+                    // An error thrown by the handler itself still runs `finally`
+                    if finally.is_some() {
+                        handler_pos = self.code.len();
+                        self.emit(Opcode::PushHandler(0, slot)); // placeholder
+                        pending = Some(handler_pos);
+                    }
+                    // The handler starts with the error value on the stack
+                    self.begin_scope();
+                    self.declare(var);
+                    self.store(var);
+                    self.compile_protected(handler, finally);
+                    self.end_scope();
+                    if finally.is_some() {
+                        self.emit(Opcode::PopHandler);
+                    }
+                    self.code[skip_pos] = Opcode::Jump(self.code.len());
+                }
+
+                if let Some(finally) = finally {
+                    self.compile_block(finally);
+                    if let Some(pos) = pending {
+                        let end_pos = self.code.len();
+                        self.emit(Opcode::Jump(0)); // placeholder
+                        self.code[pos] = Opcode::PushHandler(self.code.len(), slot);
+                        // Run `finally` with the error set aside, then throw it again
+                        self.begin_scope();
+                        let error_slot = self.declare_hidden("(pending error)");
+                        self.emit(Opcode::StoreLocal(error_slot));
+                        self.compile_block(finally);
+                        self.emit(Opcode::LoadLocal(error_slot));
+                        self.emit(Opcode::Throw);
+                        self.end_scope();
+                        self.code[end_pos] = Opcode::Jump(self.code.len());
+                    }
                 }
-                self.code.push(Opcode::Return);
             }
-            _ => panic!("Unsupported expression"),
         }
     }
 
-    fn add_constant(&mut self, value: Expr) -> usize {
-        let idx = self.constants.len();
-        self.constants.push(value);
-        idx
+    /// Compiles a block that runs with a handler installed; jumps out of it run `finally` first.
+    fn compile_protected(&mut self, body: &[Spanned<Expr>], finally: &Option<Vec<Spanned<Expr>>>) {
+        let loop_depth = self.scope().loops.len();
+        self.scope().tries.push(Try {
+            finally: finally.clone(),
+            loop_depth,
+        });
+        self.compile_block(body);
+        self.scope().tries.pop();
     }
 
-    pub fn get_code(&self) -> &Vec<Opcode> {
-        &self.code
+    /// For a jump out of the innermost `count` tries: uninstalls their handlers and runs their
+    /// `finally` bodies, innermost first.
+    fn exit_tries(&mut self, count: usize) {
+        let tries = std::mem::take(&mut self.scope().tries);
+        for i in (tries.len() - count..tries.len()).rev() {
+            self.emit(Opcode::PopHandler);
+            if let Some(finally) = &tries[i].finally {
+                // A jump inside `finally` only leaves the tries around this one
+                self.scope().tries = tries[..i].to_vec();
+                self.compile_block(finally);
+            }
+        }
+        self.scope().tries = tries;
     }
 
-    pub fn get_constants(&self) -> &Vec<Expr> {
-        &self.constants
+    /// Compiles `body` in its own block scope; its locals go out of scope at the end.
+    fn compile_block(&mut self, body: &[Spanned<Expr>]) {
+        self.begin_scope();
+        for stmt in body {
+            self.compile_stmt(stmt);
+        }
+        self.end_scope();
     }
-}// src/compiler.rs - FalconCore Bytecode Compiler (Basic)
-use crate::parser::Expr;
 
-#[derive(Debug, Clone)]
-pub enum Opcode {
-    LoadConst(usize), // index in constant table
-    Add,
-    Print,
-    Return,
-}
+    /// The body of a loop whose next iteration starts at `loop_start`. If the loop has a
+    /// variable, its value is on the stack and gets bound fresh for every iteration.
+    fn compile_loop_body(&mut self, loop_start: usize, var: Option<&String>, body: &[Spanned<Expr>]) {
+        self.begin_scope();
+        let body_slot = self.scope().locals.len();
+        self.scope().loops.push(Loop {
+            continue_target: loop_start,
+            body_slot,
+            break_jumps: vec![],
+        });
+        if let Some(var) = var {
+            self.declare(var);
+            self.store(var);
+        }
+        for stmt in body {
+            self.compile_stmt(stmt);
+        }
+        self.end_scope();
+        self.emit(Opcode::Jump(loop_start));
 
-pub struct Compiler {
-    constants: Vec<Expr>,
-    code: Vec<Opcode>,
-}
+        let inner = self.scope().loops.pop().expect("pushed above");
+        for pos in inner.break_jumps {
+            self.code[pos] = Opcode::Jump(self.code.len());
+        }
+    }
 
-impl Compiler {
-    pub fn new() -> Self {
-        Compiler {
-            constants: vec![],
-            code: vec![],
+    fn begin_scope(&mut self) {
+        self.scope().depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.scope();
+        scope.depth -= 1;
+        let depth = scope.depth;
+        let first_dropped = scope.locals.iter().position(|local| local.depth > depth);
+        if let Some(first) = first_dropped {
+            let captured = scope.locals[first..].iter().any(|local| local.captured);
+            scope.locals.truncate(first);
+            if captured {
+                self.emit(Opcode::CloseUpvalues(first));
+            }
         }
     }
 
-    pub fn compile(&mut self, ast: Vec<Expr>) {
-        for expr in ast {
-            self.compile_expr(&expr);
+    fn scope(&mut self) -> &mut FunctionScope {
+        self.scopes.last_mut().expect("the script scope is never popped")
+    }
+
+    /// Declares a local in the current block, unless this is a script-level global.
+    fn declare(&mut self, name: &str) {
+        if self.scopes.len() > 1 || self.scope().depth > 0 {
+            self.scope().declare(name);
         }
     }
 
-    fn compile_expr(&mut self, expr: &Expr) {
-        match expr {
-            Expr::Number(n) => {
-                let idx = self.add_constant(Expr::Number(*n));
-                self.code.push(Opcode::LoadConst(idx));
-            }
-            Expr::String(s) => {
-                let idx = self.add_constant(Expr::String(s.clone()));
-                self.code.push(Opcode::LoadConst(idx));
-            }
-            Expr::Binary { left, op, right } => {
-                self.compile_expr(left);
-                self.compile_expr(right);
-                match op {
-                    TokenType::Plus => self.code.push(Opcode::Add),
-                    _ => panic!("Unsupported operator"),
-                }
-            }
-            Expr::Print { expr } => {
-                self.compile_expr(expr);
-                self.code.push(Opcode::Print);
+    /// A compiler-generated local; the name can't collide with a Falcon identifier.
+    fn declare_hidden(&mut self, name: &str) -> usize {
+        self.scope().declare(name)
+    }
+
+    fn place(&mut self, name: &str) -> Place {
+        let innermost = self.scopes.len() - 1;
+        if let Some(slot) = self.scopes[innermost].resolve(name) {
+            return Place::Local(slot);
+        }
+        match self.resolve_upvalue(innermost, name) {
+            Some(index) => Place::Upvalue(index),
+            None => Place::Global,
+        }
+    }
+
+    /// Finds `name` in an enclosing function and threads a capture down to `scopes[level]`.
+    fn resolve_upvalue(&mut self, level: usize, name: &str) -> Option<usize> {
+        if level == 0 {
+            return None;
+        }
+        if let Some(slot) = self.scopes[level - 1].resolve(name) {
+            self.scopes[level - 1].locals[slot].captured = true;
+            return Some(self.scopes[level].capture(true, slot));
+        }
+        let index = self.resolve_upvalue(level - 1, name)?;
+        Some(self.scopes[level].capture(false, index))
+    }
+
+    fn load(&mut self, name: &str) {
+        let op = match self.place(name) {
+            Place::Local(slot) => Opcode::LoadLocal(slot),
+            Place::Upvalue(index) => Opcode::LoadUpvalue(index),
+            Place::Global => Opcode::LoadGlobal(self.global_slot(name)),
+        };
+        self.emit(op);
+    }
+
+    fn store(&mut self, name: &str) {
+        let op = match self.place(name) {
+            Place::Local(slot) => Opcode::StoreLocal(slot),
+            Place::Upvalue(index) => Opcode::StoreUpvalue(index),
+            Place::Global => Opcode::StoreGlobal(self.global_slot(name)),
+        };
+        self.emit(op);
+    }
+
+    /// Records whether the binding `name` now resolves to was declared `secure`.
+    fn mark_secure(&mut self, name: &str, secure: bool) {
+        let innermost = self.scopes.len() - 1;
+        if let Some(slot) = self.scopes[innermost].resolve(name) {
+            self.scopes[innermost].locals[slot].secure = secure;
+            return;
+        }
+        let slot = self.global_slot(name);
+        if secure {
+            self.secure_globals.insert(slot);
+        } else {
+            self.secure_globals.remove(&slot);
+        }
+    }
+
+    /// Whether the binding `name` resolves to here, local, captured or global, is secure. Follows
+    /// the same lookup as `place`, so a shadowing binding never inherits another's secrecy.
+    pub fn is_secure(&self, name: &str) -> bool {
+        for scope in self.scopes.iter().rev() {
+            if let Some(slot) = scope.resolve(name) {
+                return scope.locals[slot].secure;
             }
-            _ => panic!("Unsupported expression in compiler"),
         }
+        self.global_slots.get(name).is_some_and(|slot| self.secure_globals.contains(slot))
+    }
+
+    /// The slot of a global, assigned on first use.
+    fn global_slot(&mut self, name: &str) -> usize {
+        if let Some(slot) = self.global_slots.get(name) {
+            return *slot;
+        }
+        self.globals.push(name.to_string());
+        self.global_slots.insert(name.to_string(), self.globals.len() - 1);
+        self.globals.len() - 1
     }
 
-    fn add_constant(&mut self, value: Expr) -> usize {
+    fn add_constant(&mut self, value: Value) -> usize {
         let idx = self.constants.len();
         self.constants.push(value);
         idx
     }
 
-    pub fn get_code(&self) -> &Vec<Opcode> {
-        &self.code
+    /// The compiled program, ready for the VM.
+    pub fn program(&self) -> Program {
+        Program {
+            constants: self.constants.clone(),
+            code: self.code.clone(),
+            globals: self.globals.clone(),
+            // The source line of each instruction, for runtime error reports
+            lines: self.spans.iter().map(|span| span.line).collect(),
+            local_count: self.scopes[0].slot_count,
+        }
+    }
+}
+
+/// Whether a statement leaves a value on the stack that nobody consumes.
+fn produces_value(stmt: &Expr) -> bool {
+    matches!(
+        stmt,
+        Expr::Number(_)
+            | Expr::Float(_)
+            | Expr::Bool(_)
+            | Expr::String(_)
+            | Expr::Identifier { .. }
+            | Expr::Binary { .. }
+            | Expr::Unary { .. }
+            | Expr::Call { .. }
+            | Expr::Member { .. }
+            | Expr::Interpolation(_)
+            | Expr::List(_)
+            | Expr::Map(_)
+            | Expr::Index { .. }
+    )
+}
+
+fn binary_opcode(op: &TokenType) -> Opcode {
+    match op {
+        TokenType::Plus => Opcode::Add,
+        TokenType::Minus => Opcode::Sub,
+        TokenType::Star => Opcode::Mul,
+        TokenType::Slash => Opcode::Div,
+        TokenType::Percent => Opcode::Mod,
+        TokenType::EqualEqual => Opcode::Equal,
+        TokenType::NotEqual => Opcode::NotEqual,
+        TokenType::Greater => Opcode::Greater,
+        TokenType::Less => Opcode::Less,
+        TokenType::GreaterEqual => Opcode::GreaterEqual,
+        TokenType::LessEqual => Opcode::LessEqual,
+        _ => panic!("Unsupported operator"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::vm::VM;
+
+    /// What the script returns, as `print` would show it.
+    fn eval(source: &str) -> String {
+        let ast = Parser::new(Lexer::new(source)).parse().expect("test scripts parse");
+        let mut compiler = Compiler::new();
+        compiler.compile(ast);
+        VM::new(compiler.program()).run().expect("test scripts run").to_string()
+    }
+
+    #[test]
+    fn assignment_and_compound_assignment() {
+        let source = "let x = 10
+x += 5
+x -= 3
+x *= 4
+x /= 6
+let s = \"a\"
+s += \"b\"
+let y = 1
+y = y + x
+return [x, s, y]";
+        assert_eq!(eval(source), "[8, \"ab\", 9]");
     }
 
-    pub fn get_constants(&self) -> &Vec<Expr> {
-        &self.constants
+    #[test]
+    fn compound_assignment_to_locals_and_elements() {
+        let source = "fn f(n) {
+    let total = n
+    total *= 2
+    return total
+}
+let items = [1, 2]
+items[1] += 10
+let m = { \"k\": 1 }
+m[\"k\"] -= 3
+return [f(4), items, m]";
+        assert_eq!(eval(source), "[8, [1, 12], {\"k\": -2}]");
+    }
+
+    #[test]
+    fn elseif_ladders_take_the_first_true_branch() {
+        let source = "fn pick(n) {
+    if n < 0 {
+        return \"negative\"
+    } elseif n == 0 {
+        return \"zero\"
+    } elseif n < 10 {
+        return \"small\"
+    } else {
+        return \"large\"
+    }
+}
+fn classic(n) {
+    let out = \"odd\"
+    if n % 2 == 0
+        out = \"even\"
+    endif
+    return out
+}
+return [pick(-1), pick(0), pick(5), pick(50), classic(2), classic(3)]";
+        assert_eq!(eval(source), "[\"negative\", \"zero\", \"small\", \"large\", \"even\", \"odd\"]");
     }
 }
-Opcode::NetworkScan,
@@ -1,52 +1,215 @@
 // src/compiler_aot.rs - FalconCore AOT Compiler (Cranelift backend)
+// Lowers the integer/string subset of the AST to a native `main` that links against libc.
+use crate::lexer::TokenType;
+use crate::parser::Expr;
+use crate::span::Spanned;
 use cranelift::codegen::ir::{types, AbiParam, Function, UserFuncName};
-use cranelift::codegen::Context;
+use cranelift::codegen::{settings, Context};
 use cranelift::frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
 use cranelift::prelude::*;
-use cranelift_module::{DataId, Linkage, Module};
-use cranelift_object::{ObjectModule, ObjectProduct};
+use cranelift_module::{DataDescription, FuncId, Linkage, Module};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use std::collections::HashMap;
 
-pub fn compile_to_executable(ast: &Vec<Expr>) -> Result<Vec<u8>, String> {
-    let mut module = ObjectModule::new();
-    let mut ctx = module.make_context();
-    let mut builder_ctx = FunctionBuilderContext::new();
+struct AotContext {
+    module: ObjectModule,
+    variables: HashMap<String, Variable>,
+    printf: FuncId,
+    puts: FuncId,
+    int_format: Option<cranelift_module::DataId>,
+    strings: usize,
+}
+
+pub fn compile_to_executable(ast: &Vec<Spanned<Expr>>) -> Result<Vec<u8>, String> {
+    let mut flags = settings::builder();
+    flags.set("is_pic", "true").map_err(|e| e.to_string())?;
+    let isa = cranelift_native::builder()
+        .map_err(|e| e.to_string())?
+        .finish(settings::Flags::new(flags))
+        .map_err(|e| e.to_string())?;
+
+    let builder = ObjectBuilder::new(isa, "falconcore", cranelift_module::default_libcall_names())
+        .map_err(|e| e.to_string())?;
+    let mut module = ObjectModule::new(builder);
+    let pointer = module.target_config().pointer_type();
+
+    let mut printf_sig = module.make_signature();
+    printf_sig.params.push(AbiParam::new(pointer));
+    printf_sig.params.push(AbiParam::new(types::I64));
+    printf_sig.returns.push(AbiParam::new(types::I32));
+    let printf = module
+        .declare_function("printf", Linkage::Import, &printf_sig)
+        .map_err(|e| e.to_string())?;
+
+    let mut puts_sig = module.make_signature();
+    puts_sig.params.push(AbiParam::new(pointer));
+    puts_sig.returns.push(AbiParam::new(types::I32));
+    let puts = module
+        .declare_function("puts", Linkage::Import, &puts_sig)
+        .map_err(|e| e.to_string())?;
+
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I32));
 
-    let sig = module.make_signature();
-    let mut func = Function::with_name_signature(UserFuncName::user(0, 0), sig);
-    let mut builder = FunctionBuilder::new(&mut func, &mut ctx.func, &mut builder_ctx);
+    let mut aot = AotContext {
+        module,
+        variables: HashMap::new(),
+        printf,
+        puts,
+        int_format: None,
+        strings: 0,
+    };
 
-    let mut variables = HashMap::new();
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, 0), sig.clone());
+    let mut builder_ctx = FunctionBuilderContext::new();
+    {
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+
+        let entry = builder.create_block();
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
 
-    // Simple "main" function
-    let entry = builder.create_block();
-    builder.append_block_params_for_function_params(entry);
-    builder.switch_to_block(entry);
-    builder.seal_block(entry);
+        for expr in ast {
+            compile_stmt_to_cranelift(&mut aot, &mut builder, expr)?;
+        }
 
-    // Compile AST to Cranelift IR
-    for expr in ast {
-        compile_expr_to_cranelift(&mut builder, expr, &mut variables);
+        let zero = builder.ins().iconst(types::I32, 0);
+        builder.ins().return_(&[zero]);
+        builder.finalize();
     }
 
-    builder.finalize();
+    let id = aot
+        .module
+        .declare_function("main", Linkage::Export, &sig)
+        .map_err(|e| e.to_string())?;
+    aot.module.define_function(id, &mut ctx).map_err(|e| e.to_string())?;
+
+    let product = aot.module.finish();
+    product.object.write().map_err(|e| e.to_string())
+}
+
+fn compile_stmt_to_cranelift(
+    aot: &mut AotContext,
+    builder: &mut FunctionBuilder,
+    expr: &Spanned<Expr>,
+) -> Result<(), String> {
+    match &expr.node {
+        // Compiled code can't keep a value secret: it would sit in the binary as a plain constant
+        // and print in the clear, so secure bindings are left to the interpreter
+        Expr::Let { is_secure: true, name, .. } => {
+            return Err(format!(
+                "AOT: secure binding '{}' can't be compiled to native code; run the script instead (line {})",
+                name.node, expr.span.line
+            ));
+        }
+        Expr::Let { name, value, .. } => {
+            let name = &name.node;
+            let val = compile_expr_to_cranelift(aot, builder, value)?;
+            let var = match aot.variables.get(name) {
+                Some(var) => *var,
+                None => {
+                    let var = Variable::new(aot.variables.len());
+                    builder.declare_var(var, types::I64);
+                    aot.variables.insert(name.clone(), var);
+                    var
+                }
+            };
+            builder.def_var(var, val);
+        }
+        Expr::Print { expr } => {
+            let pointer = aot.module.target_config().pointer_type();
+            if let Expr::String(s) = &expr.node {
+                let data = define_string(aot, s)?;
+                let gv = aot.module.declare_data_in_func(data, builder.func);
+                let ptr = builder.ins().global_value(pointer, gv);
+                let callee = aot.module.declare_func_in_func(aot.puts, builder.func);
+                builder.ins().call(callee, &[ptr]);
+            } else {
+                let val = compile_expr_to_cranelift(aot, builder, expr)?;
+                let format = match aot.int_format {
+                    Some(id) => id,
+                    None => {
+                        let id = define_string(aot, "%lld\n")?;
+                        aot.int_format = Some(id);
+                        id
+                    }
+                };
+                let gv = aot.module.declare_data_in_func(format, builder.func);
+                let ptr = builder.ins().global_value(pointer, gv);
+                let callee = aot.module.declare_func_in_func(aot.printf, builder.func);
+                builder.ins().call(callee, &[ptr, val]);
+            }
+        }
+        _ => {
+            compile_expr_to_cranelift(aot, builder, expr)?;
+        }
+    }
+    Ok(())
+}
 
-    let id = module.declare_function("main", Linkage::Export, &ctx.func.signature).unwrap();
-    module.define_function(id, &ctx).unwrap();
+fn compile_expr_to_cranelift(
+    aot: &mut AotContext,
+    builder: &mut FunctionBuilder,
+    expr: &Spanned<Expr>,
+) -> Result<Value, String> {
+    let line = expr.span.line;
+    match &expr.node {
+        Expr::Number(n) => Ok(builder.ins().iconst(types::I64, *n)),
+        Expr::Identifier(name) => match aot.variables.get(name) {
+            Some(var) => Ok(builder.use_var(*var)),
+            None => Err(format!("AOT: undefined variable '{}' (line {})", name, line)),
+        },
+        Expr::Binary { left, op, right } => {
+            let l = compile_expr_to_cranelift(aot, builder, left)?;
+            let r = compile_expr_to_cranelift(aot, builder, right)?;
+            match op {
+                TokenType::Plus => Ok(builder.ins().iadd(l, r)),
+                TokenType::Minus => Ok(builder.ins().isub(l, r)),
+                _ => Err(format!("AOT: unsupported operator {:?} (line {})", op, line)),
+            }
+        }
+        other => Err(format!("AOT: unsupported expression {:?} (line {})", other, line)),
+    }
+}
 
-    let product = module.finish();
-    let bytes = product.object.write().unwrap();
+fn define_string(aot: &mut AotContext, s: &str) -> Result<cranelift_module::DataId, String> {
+    let name = format!("falcon_str_{}", aot.strings);
+    aot.strings += 1;
 
-    Ok(bytes)
+    let id = aot
+        .module
+        .declare_data(&name, Linkage::Local, false, false)
+        .map_err(|e| e.to_string())?;
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(0);
+    let mut data = DataDescription::new();
+    data.define(bytes.into_boxed_slice());
+    aot.module.define_data(id, &data).map_err(|e| e.to_string())?;
+    Ok(id)
 }
 
-fn compile_expr_to_cranelift(builder: &mut FunctionBuilder, expr: &Expr, variables: &mut HashMap<String, Variable>) {
-    // Very simple implementation - expand later
-    match expr {
-        Expr::Number(n) => {
-            let val = builder.ins().iconst(types::I64, *n as i64);
-            builder.ins().return_(&[val]);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn compile(source: &str) -> Result<Vec<u8>, String> {
+        let ast = Parser::new(Lexer::new(source)).parse().expect("test scripts parse");
+        compile_to_executable(&ast)
+    }
+
+    #[test]
+    fn compiles_the_integer_subset() {
+        assert!(!compile("let x = 40\nprint x + 2\nprint \"done\"").unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_secure_bindings() {
+        for source in ["secure let pin = 1234\nprint pin", "secure const pin = 1234"] {
+            let error = compile(source).unwrap_err();
+            assert!(error.contains("secure binding 'pin'"), "{}", error);
         }
-        // Add more cases (print, add, if, loop etc.)
-        _ => {}
     }
-      }
+}
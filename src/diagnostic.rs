@@ -0,0 +1,54 @@
+// src/diagnostic.rs - FalconCore Diagnostics (file:line:column + source snippet with caret)
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    pub expected: Option<String>,
+    pub found: Option<String>,
+    pub snippet: String,
+}
+
+impl Diagnostic {
+    pub fn new(file: &str, source: &str, line: usize, column: usize, message: String) -> Self {
+        Diagnostic {
+            file: file.to_string(),
+            line,
+            column,
+            message,
+            expected: None,
+            found: None,
+            snippet: render_snippet(source, line, column),
+        }
+    }
+
+    pub fn with_expected(mut self, expected: String, found: String) -> Self {
+        self.expected = Some(expected);
+        self.found = Some(found);
+        self
+    }
+}
+
+/// Renders the offending source line with a caret under `column`, rustc-style.
+fn render_snippet(source: &str, line: usize, column: usize) -> String {
+    let text = source.lines().nth(line.saturating_sub(1)).unwrap_or("");
+    let gutter = " ".repeat(line.to_string().len());
+    // Keep tabs so the caret lines up with the original indentation
+    let pad: String = text
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!("{} |\n{} | {}\n{} | {}^", gutter, line, text, gutter, pad)
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "error: {}", self.message)?;
+        writeln!(f, " --> {}:{}:{}", self.file, self.line, self.column)?;
+        write!(f, "{}", self.snippet)
+    }
+}
@@ -0,0 +1,201 @@
+// src/error.rs - FalconCore runtime errors (kind + source line + Falcon backtrace)
+use crate::methods;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Type,
+    Value,
+    DivisionByZero,
+    Undefined,
+    Index,
+    Overflow,
+    Arity,
+    StackUnderflow,
+    // Calls nested deeper than the VM allows
+    StackOverflow,
+    Io,
+    // Live heap over the configured limit
+    Memory,
+    // Malformed bytecode, rejected before it runs
+    Verify,
+    // Raised by a `throw` statement
+    Thrown,
+}
+
+impl ErrorKind {
+    /// The name shown in error reports, e.g. `TypeError`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Type => "TypeError",
+            ErrorKind::Value => "ValueError",
+            ErrorKind::DivisionByZero => "DivisionByZero",
+            ErrorKind::Undefined => "UndefinedName",
+            ErrorKind::Index => "IndexError",
+            ErrorKind::Overflow => "OverflowError",
+            ErrorKind::Arity => "ArityError",
+            ErrorKind::StackUnderflow => "StackUnderflow",
+            ErrorKind::StackOverflow => "StackOverflow",
+            ErrorKind::Io => "IoError",
+            ErrorKind::Memory => "MemoryError",
+            ErrorKind::Verify => "VerifyError",
+            ErrorKind::Thrown => "Error",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<ErrorKind> {
+        const ALL: [ErrorKind; 13] = [
+            ErrorKind::Type,
+            ErrorKind::Value,
+            ErrorKind::DivisionByZero,
+            ErrorKind::Undefined,
+            ErrorKind::Index,
+            ErrorKind::Overflow,
+            ErrorKind::Arity,
+            ErrorKind::StackUnderflow,
+            ErrorKind::StackOverflow,
+            ErrorKind::Io,
+            ErrorKind::Memory,
+            ErrorKind::Verify,
+            ErrorKind::Thrown,
+        ];
+        ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// One Falcon call that was active when the error was raised.
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    pub function: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub line: usize,
+    // Innermost call first; the last entry is always the top-level script
+    pub backtrace: Vec<TraceFrame>,
+    // What `throw` was given, handed back unchanged to `catch`
+    pub value: Option<Value>,
+}
+
+impl RuntimeError {
+    /// An error without a location yet; the VM fills in the line and backtrace when it surfaces.
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        RuntimeError {
+            kind,
+            message: message.into(),
+            line: 0,
+            backtrace: vec![],
+            value: None,
+        }
+    }
+
+    /// The error raised by `throw value`. A map with string `kind` and `message` entries, like the
+    /// ones `catch` binds for runtime errors, keeps its kind when rethrown.
+    pub fn thrown(value: Value) -> Self {
+        let mut line = 0;
+        let (kind, message) = match &value {
+            Value::Map(entries) => {
+                let entries = entries.borrow();
+                // A rethrown error is still reported where it first happened
+                if let Some(Value::Int(n)) = entries.get("line") {
+                    line = *n as usize;
+                }
+                match (entries.get("kind"), entries.get("message")) {
+                    (Some(Value::Str(kind)), Some(Value::Str(message))) => match ErrorKind::from_name(kind) {
+                        Some(kind) => (kind, message.to_string()),
+                        None => (ErrorKind::Thrown, format!("{}: {}", kind, message)),
+                    },
+                    _ => (ErrorKind::Thrown, String::new()),
+                }
+            }
+            // Rendered from `value` only if the error is reported, so a caught throw never pays for it
+            _ => (ErrorKind::Thrown, String::new()),
+        };
+        RuntimeError {
+            line,
+            value: Some(value),
+            ..RuntimeError::new(kind, message)
+        }
+    }
+
+    /// What `catch e` binds: the thrown value, or a `{"kind", "message", "line"}` map for runtime errors.
+    pub fn to_value(&self) -> Value {
+        if let Some(value) = &self.value {
+            return value.clone();
+        }
+        methods::map(HashMap::from([
+            ("kind".to_string(), Value::string(self.kind.name())),
+            ("message".to_string(), Value::string(&self.message)),
+            ("line".to_string(), Value::Int(self.line as i64)),
+        ]))
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) if self.message.is_empty() => write!(f, "{}: {} (line {})", self.kind, value, self.line)?,
+            _ => write!(f, "{}: {} (line {})", self.kind, self.message, self.line)?,
+        }
+        // Runaway recursion repeats one frame many times over; show it once with a count
+        let mut frames = self.backtrace.iter().peekable();
+        while let Some(frame) = frames.next() {
+            write!(f, "\n    at {} (line {})", frame.function, frame.line)?;
+            let mut repeats = 0;
+            while frames.next_if(|next| next.function == frame.function && next.line == frame.line).is_some() {
+                repeats += 1;
+            }
+            if repeats > 0 {
+                write!(f, "\n    ... the same call {} more times", repeats)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_frames_are_folded() {
+        let frame = |function: &str, line| TraceFrame { function: function.to_string(), line };
+        let error = RuntimeError {
+            line: 2,
+            backtrace: vec![frame("f", 2), frame("f", 2), frame("f", 2), frame("<script>", 5)],
+            ..RuntimeError::new(ErrorKind::StackOverflow, "too deep")
+        };
+        assert_eq!(
+            error.to_string(),
+            "StackOverflow: too deep (line 2)\n    at f (line 2)\n    ... the same call 2 more times\n    at <script> (line 5)"
+        );
+    }
+
+    #[test]
+    fn caught_errors_round_trip_through_throw() {
+        let error = RuntimeError {
+            line: 7,
+            ..RuntimeError::new(ErrorKind::Index, "out of range")
+        };
+        let rethrown = RuntimeError::thrown(error.to_value());
+        assert_eq!((rethrown.kind, rethrown.message.as_str(), rethrown.line), (ErrorKind::Index, "out of range", 7));
+
+        let thrown = RuntimeError::thrown(Value::string("boom"));
+        assert_eq!(thrown.kind, ErrorKind::Thrown);
+        assert_eq!(thrown.to_string(), "Error: boom (line 0)");
+    }
+}
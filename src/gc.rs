@@ -0,0 +1,474 @@
+// src/gc.rs - FalconCore heap (allocation accounting, mark-and-sweep collector, heap limits)
+//
+// Values stay reference counted, so almost everything is freed the moment it becomes unreachable.
+// What refcounting can't free is a cycle: a list that contains itself, or a closure whose upvalue
+// holds the closure. Every list, map, closure and string a running VM allocates is registered
+// here. A collection marks everything reachable from the VM's roots, then empties the lists, maps
+// and closures that are still alive but weren't reached, which drops the cycles they formed.
+//
+// A heap belongs to one VM and is installed for the current thread while that VM runs, so the
+// allocation helpers in `methods` and `value` can register objects without a handle to the VM.
+use crate::error::{ErrorKind, RuntimeError};
+use crate::value::{Closure, Upvalue, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::rc::{Rc, Weak};
+
+/// Limits for one VM's heap.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapConfig {
+    /// Estimated bytes to allocate before the first collection; later ones follow the live size.
+    pub initial_threshold: usize,
+    /// Largest live heap, in estimated bytes, before the script fails with a MemoryError.
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for HeapConfig {
+    fn default() -> Self {
+        HeapConfig {
+            initial_threshold: 1 << 20,
+            max_bytes: None,
+        }
+    }
+}
+
+/// What `gc.stats()` reports. Live figures are as of the last collection.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+    pub collections: usize,
+    // Objects reclaimed, whether refcounting got there first or a collection broke their cycle
+    pub freed: usize,
+    pub live_objects: usize,
+    pub live_bytes: usize,
+    // Estimated bytes allocated since the last collection
+    pub allocated: usize,
+    pub threshold: usize,
+}
+
+/// A registered allocation; weak, so tracking never keeps anything alive.
+enum Object {
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<HashMap<String, Value>>>),
+    Closure(Weak<Closure>),
+    Str(Weak<str>),
+}
+
+pub struct Heap {
+    config: HeapConfig,
+    objects: Vec<Object>,
+    stats: HeapStats,
+}
+
+impl Heap {
+    pub fn new(config: HeapConfig) -> Self {
+        Heap {
+            config,
+            objects: vec![],
+            stats: HeapStats {
+                threshold: config.initial_threshold,
+                ..HeapStats::default()
+            },
+        }
+    }
+
+    fn grow(&mut self, bytes: usize) {
+        self.stats.allocated += bytes;
+        if self.stats.live_bytes + self.stats.allocated >= self.stats.threshold {
+            PENDING.set(true);
+        }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new(HeapConfig::default())
+    }
+}
+
+thread_local! {
+    static HEAP: RefCell<Option<Heap>> = const { RefCell::new(None) };
+    // Set once allocation passes the threshold or a script asks; the VM collects at its next
+    // safe point, where every live value is reachable from its roots
+    static PENDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Makes `heap` the one new objects are registered with, until `uninstall`.
+pub fn install(heap: Heap) {
+    PENDING.set(false);
+    HEAP.with(|slot| *slot.borrow_mut() = Some(heap));
+}
+
+pub fn uninstall() -> Heap {
+    PENDING.set(false);
+    HEAP.with(|slot| slot.borrow_mut().take()).unwrap_or_default()
+}
+
+/// Whether the running VM should collect at its next safe point.
+pub fn pending() -> bool {
+    PENDING.get()
+}
+
+/// Asks for a collection at the VM's next safe point, as `gc.collect()` does.
+pub fn request() {
+    PENDING.set(true);
+}
+
+/// The installed heap's counters, or None outside a running VM.
+pub fn stats() -> Option<(HeapStats, Option<usize>)> {
+    HEAP.with(|slot| slot.borrow().as_ref().map(|heap| (heap.stats, heap.config.max_bytes)))
+}
+
+/// Outside a running VM (while compiling, say) nothing is tracked.
+fn register(object: impl FnOnce() -> Object, bytes: usize) {
+    HEAP.with(|slot| {
+        if let Some(heap) = slot.borrow_mut().as_mut() {
+            heap.objects.push(object());
+            heap.grow(bytes);
+        }
+    });
+}
+
+pub fn track_list(items: &Rc<RefCell<Vec<Value>>>) {
+    let bytes = list_size(&items.borrow());
+    register(|| Object::List(Rc::downgrade(items)), bytes);
+}
+
+pub fn track_map(entries: &Rc<RefCell<HashMap<String, Value>>>) {
+    let bytes = map_size(&entries.borrow());
+    register(|| Object::Map(Rc::downgrade(entries)), bytes);
+}
+
+pub fn track_closure(closure: &Rc<Closure>) {
+    register(|| Object::Closure(Rc::downgrade(closure)), closure_size(closure));
+}
+
+pub fn track_str(s: &Rc<str>) {
+    register(|| Object::Str(Rc::downgrade(s)), s.len());
+}
+
+/// Accounts for an existing list or map growing, e.g. `push` or a new map key.
+pub fn grow(bytes: usize) {
+    HEAP.with(|slot| {
+        if let Some(heap) = slot.borrow_mut().as_mut() {
+            heap.grow(bytes);
+        }
+    });
+}
+
+// Size estimates: what the object owns directly, not what its elements point to
+fn list_size(items: &Vec<Value>) -> usize {
+    mem::size_of::<RefCell<Vec<Value>>>() + items.capacity() * mem::size_of::<Value>()
+}
+
+fn map_size(entries: &HashMap<String, Value>) -> usize {
+    let entry = mem::size_of::<String>() + mem::size_of::<Value>();
+    mem::size_of::<RefCell<HashMap<String, Value>>>()
+        + entries.capacity() * entry
+        + entries.keys().map(String::len).sum::<usize>()
+}
+
+fn closure_size(closure: &Closure) -> usize {
+    mem::size_of::<Closure>() + closure.upvalues.len() * mem::size_of::<RefCell<Upvalue>>()
+}
+
+fn address<T: ?Sized>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+/// Everything reached so far from the roots. Values still to be scanned wait in `pending`
+/// instead of being recursed into, so deeply nested data can't overflow the stack.
+#[derive(Default)]
+pub struct Marker {
+    reached: HashSet<usize>,
+    pending: Vec<Value>,
+}
+
+impl Marker {
+    pub fn value(&mut self, value: &Value) {
+        self.pending.push(value.clone());
+        self.drain();
+    }
+
+    pub fn closure(&mut self, closure: &Rc<Closure>) {
+        self.pending.push(Value::Closure(closure.clone()));
+        self.drain();
+    }
+
+    pub fn upvalue(&mut self, upvalue: &Rc<RefCell<Upvalue>>) {
+        self.reach_upvalue(upvalue);
+        self.drain();
+    }
+
+    fn drain(&mut self) {
+        while let Some(value) = self.pending.pop() {
+            match &value {
+                Value::List(items) if self.reached.insert(address(items)) => {
+                    self.pending.extend(items.borrow().iter().cloned());
+                }
+                Value::Map(entries) if self.reached.insert(address(entries)) => {
+                    self.pending.extend(entries.borrow().values().cloned());
+                }
+                Value::Closure(closure) if self.reached.insert(address(closure)) => {
+                    for upvalue in &closure.upvalues {
+                        self.reach_upvalue(upvalue);
+                    }
+                }
+                Value::Str(s) => {
+                    self.reached.insert(address(s));
+                }
+                Value::Secret(secret) => self.pending.push(secret.expose().clone()),
+                _ => {}
+            }
+        }
+    }
+
+    fn reach_upvalue(&mut self, upvalue: &Rc<RefCell<Upvalue>>) {
+        if self.reached.insert(address(upvalue)) {
+            // Open upvalues point at frame slots, which are roots already
+            if let Upvalue::Closed(value) = &*upvalue.borrow() {
+                self.pending.push(value.clone());
+            }
+        }
+    }
+
+    fn has(&self, address: usize) -> bool {
+        self.reached.contains(&address)
+    }
+}
+
+/// Runs a collection on the installed heap. `mark_roots` must mark everything the VM can still
+/// reach; anything else that is alive is only kept alive by a cycle. Fails if the live heap is
+/// over the configured limit afterwards.
+pub fn collect(mark_roots: impl FnOnce(&mut Marker)) -> Result<(), RuntimeError> {
+    PENDING.set(false);
+    let Some(mut heap) = HEAP.with(|slot| slot.borrow_mut().take()) else {
+        return Ok(());
+    };
+    let mut marker = Marker::default();
+    mark_roots(&mut marker);
+
+    let mut live = Vec::with_capacity(heap.objects.len());
+    let mut garbage = vec![];
+    let (mut live_objects, mut live_bytes, mut freed) = (0, 0, 0);
+    for object in heap.objects.drain(..) {
+        let (reached, bytes) = match &object {
+            Object::List(weak) => match weak.upgrade() {
+                Some(items) if marker.has(address(&items)) => (true, list_size(&items.borrow())),
+                Some(items) => {
+                    garbage.push(Value::List(items));
+                    (false, 0)
+                }
+                None => (false, 0),
+            },
+            Object::Map(weak) => match weak.upgrade() {
+                Some(entries) if marker.has(address(&entries)) => (true, map_size(&entries.borrow())),
+                Some(entries) => {
+                    garbage.push(Value::Map(entries));
+                    (false, 0)
+                }
+                None => (false, 0),
+            },
+            Object::Closure(weak) => match weak.upgrade() {
+                Some(closure) if marker.has(address(&closure)) => (true, closure_size(&closure)),
+                Some(closure) => {
+                    garbage.push(Value::Closure(closure));
+                    (false, 0)
+                }
+                None => (false, 0),
+            },
+            // Strings can't form cycles; an unreached one dies with whatever garbage holds it
+            Object::Str(weak) => match weak.upgrade() {
+                Some(s) if marker.has(address(&s)) => (true, s.len()),
+                Some(_) => {
+                    live.push(object);
+                    continue;
+                }
+                None => (false, 0),
+            },
+        };
+        if reached {
+            live_objects += 1;
+            live_bytes += bytes;
+            live.push(object);
+        } else {
+            freed += 1;
+        }
+    }
+
+    // Emptying each container drops its references into the cycle; the last strong references
+    // are the ones in `garbage`, so everything goes when it does
+    for object in &garbage {
+        match object {
+            Value::List(items) => drop(mem::take(&mut *items.borrow_mut())),
+            Value::Map(entries) => drop(mem::take(&mut *entries.borrow_mut())),
+            Value::Closure(closure) => {
+                for upvalue in closure.upvalues.iter().filter(|upvalue| !marker.has(address(upvalue))) {
+                    drop(mem::replace(&mut *upvalue.borrow_mut(), Upvalue::Closed(Value::Nil)));
+                }
+            }
+            _ => {}
+        }
+    }
+    drop(garbage);
+
+    heap.objects = live;
+    let stats = &mut heap.stats;
+    stats.collections += 1;
+    stats.freed += freed;
+    stats.live_objects = live_objects;
+    stats.live_bytes = live_bytes;
+    stats.allocated = 0;
+    stats.threshold = heap.config.initial_threshold.max(live_bytes * 2);
+    if let Some(max) = heap.config.max_bytes {
+        stats.threshold = stats.threshold.min(max);
+    }
+    let over_limit = heap.config.max_bytes.filter(|&max| live_bytes > max);
+    HEAP.with(|slot| *slot.borrow_mut() = Some(heap));
+
+    match over_limit {
+        Some(max) => Err(RuntimeError::new(
+            ErrorKind::Memory,
+            format!("Heap limit exceeded: {} bytes live, limit is {}", live_bytes, max),
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::lexer::Lexer;
+    use crate::methods;
+    use crate::parser::Parser;
+    use crate::vm::VM;
+
+    /// A list holding itself, and a weak handle to check whether it is still alive.
+    fn self_cycle() -> (Value, Weak<RefCell<Vec<Value>>>) {
+        let list = methods::list(vec![]);
+        let Value::List(items) = &list else { unreachable!("methods::list builds a list") };
+        items.borrow_mut().push(list.clone());
+        let weak = Rc::downgrade(items);
+        (list, weak)
+    }
+
+    #[test]
+    fn collects_an_unreachable_cycle() {
+        install(Heap::default());
+        let (list, weak) = self_cycle();
+        drop(list);
+        assert!(weak.upgrade().is_some(), "refcounting alone can't free a cycle");
+        collect(|_| {}).unwrap();
+        assert!(weak.upgrade().is_none());
+        let stats = uninstall().stats;
+        assert_eq!((stats.collections, stats.freed), (1, 1));
+    }
+
+    #[test]
+    fn keeps_a_cycle_reachable_from_the_roots() {
+        install(Heap::default());
+        let (list, weak) = self_cycle();
+        let root = methods::list(vec![list]);
+        collect(|marker| marker.value(&root)).unwrap();
+        assert_eq!(weak.upgrade().map(|items| items.borrow().len()), Some(1));
+        drop(root);
+        collect(|_| {}).unwrap();
+        assert!(weak.upgrade().is_none());
+        uninstall();
+    }
+
+    #[test]
+    fn nothing_is_tracked_without_a_heap() {
+        let (list, weak) = self_cycle();
+        assert!(stats().is_none());
+        assert!(collect(|_| {}).is_ok());
+        // Untouched; break the cycle by hand so the test doesn't leak it
+        drop(list);
+        weak.upgrade().expect("still alive").borrow_mut().clear();
+        assert!(weak.upgrade().is_none());
+    }
+
+    fn run(source: &str, config: HeapConfig) -> Result<Value, RuntimeError> {
+        let ast = Parser::new(Lexer::new(source)).parse().expect("test scripts parse");
+        let mut compiler = Compiler::new();
+        compiler.compile(ast);
+        VM::new(compiler.program()).with_heap_config(config).run()
+    }
+
+    fn stat(stats: &Value, key: &str) -> i64 {
+        let Value::Map(entries) = stats else { panic!("gc.stats() returns a map") };
+        match entries.borrow().get(key) {
+            Some(Value::Int(n)) => *n,
+            other => panic!("{} is {:?}", key, other),
+        }
+    }
+
+    #[test]
+    fn a_running_script_reclaims_its_cycles() {
+        let source = "fn make() {
+    let a = []
+    let b = [a]
+    a.push(b)
+    fn self_ref() { return self_ref }
+    return self_ref
+}
+for i in 0..200 {
+    make()
+}
+gc.collect()
+return gc.stats()";
+        let stats = run(source, HeapConfig::default()).unwrap();
+        assert!(stat(&stats, "collections") >= 1);
+        assert!(stat(&stats, "freed") >= 600, "freed {}", stat(&stats, "freed"));
+        assert!(stat(&stats, "objects") < 50, "{} objects still live", stat(&stats, "objects"));
+    }
+
+    #[test]
+    fn collects_on_its_own_past_the_threshold() {
+        let source = "for i in 0..2000 {
+    let a = [i, i, i, i]
+    a.push(a)
+}
+return gc.stats()";
+        let config = HeapConfig {
+            initial_threshold: 4096,
+            max_bytes: None,
+        };
+        assert!(stat(&run(source, config).unwrap(), "collections") > 1);
+    }
+
+    #[test]
+    fn live_heap_over_the_limit_is_a_memory_error() {
+        let source = "let keep = []
+while true {
+    keep.push([1, 2, 3, 4, 5, 6, 7, 8])
+}";
+        let config = HeapConfig {
+            initial_threshold: 1024,
+            max_bytes: Some(64 * 1024),
+        };
+        let error = run(source, config).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Memory);
+        assert!(error.message.contains("limit is 65536"), "{}", error.message);
+    }
+
+    #[test]
+    fn a_memory_error_can_be_caught() {
+        let source = "let keep = []
+try {
+    while true {
+        keep.push([1, 2, 3, 4, 5, 6, 7, 8])
+    }
+} catch e {
+    keep = []
+    return e
+}";
+        let config = HeapConfig {
+            initial_threshold: 1024,
+            max_bytes: Some(64 * 1024),
+        };
+        let caught = run(source, config).unwrap();
+        assert!(caught.to_string().contains("MemoryError"), "{}", caught);
+    }
+}
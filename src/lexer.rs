@@ -1,12 +1,15 @@
 // src/lexer.rs - FalconCore Lexer (Fully Enhanced)
 // Supports secure let/const, fn, if/else, repeat, print, network.scan, crypto.random, time.now, wait
 
+use crate::span::Span;
 use std::iter::Peekable;
 use std::str::Chars;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     // Keywords
+    Let,
+    Const,
     SecureLet,
     SecureConst,
     Fn,
@@ -17,27 +20,48 @@ pub enum TokenType {
     EndIf,
     Repeat,
     EndRepeat,
+    While,
+    For,
+    In,
     Break,
     Continue,
+    Try,
+    Catch,
+    Finally,
+    Throw,
     Print,
+    And,
+    Or,
 
     // Built-in commands
     NetworkScan,
     CryptoRandom,
     TimeNow,
+    TimeMonotonic,
     Wait,
 
     // Literals
     Identifier(String),
     String(String),
+    // Literal text before a `${`; the embedded expression's tokens follow, then the rest of the string
+    StringPart(String),
+    // `/// text`, one token per line
+    DocComment(String),
     Number(i64),
     Float(f64),
+    True,
+    False,
+
+    // A lexical error, reported by the parser where the token is used
+    Error(String),
 
     // Operators
     Plus,
     Minus,
     Star,
     Slash,
+    Percent,
+    Bang,
     EqualEqual,
     NotEqual,
     Greater,
@@ -45,6 +69,10 @@ pub enum TokenType {
     GreaterEqual,
     LessEqual,
     Assign,
+    PlusAssign,
+    MinusAssign,
+    StarAssign,
+    SlashAssign,
 
     // Punctuation
     LParen,
@@ -54,6 +82,8 @@ pub enum TokenType {
     LBracket,
     RBracket,
     Comma,
+    Dot,
+    DotDot,
     Colon,
     Semi,
 
@@ -64,41 +94,72 @@ pub enum TokenType {
 #[derive(Debug, Clone)]
 pub struct Token {
     pub kind: TokenType,
-    pub line: usize,
-    pub column: usize,
+    pub span: Span,
 }
 
 pub struct Lexer<'a> {
+    source: &'a str,
     chars: Peekable<Chars<'a>>,
+    // Byte offset of the next character
+    offset: usize,
     line: usize,
     column: usize,
+    // Brace depth inside each `${ ... }` we're currently lexing, innermost last
+    interpolations: Vec<usize>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
         Lexer {
+            source: input,
             chars: input.chars().peekable(),
+            offset: 0,
             line: 1,
             column: 1,
+            interpolations: vec![],
         }
     }
 
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
     fn advance(&mut self) -> Option<char> {
-        self.column += 1;
-        self.chars.next()
+        let c = self.chars.next();
+        self.offset += c.map_or(0, char::len_utf8);
+        if c == Some('\n') {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        c
+    }
+
+    /// An empty span at the current position, to be extended with `span_from`.
+    fn mark(&self) -> Span {
+        Span::point(self.offset, self.line, self.column)
+    }
+
+    /// From `start` up to the current position.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.mark())
     }
 
     fn peek(&mut self) -> Option<&char> {
         self.chars.peek()
     }
 
+    /// The character after `peek()`.
+    fn peek_next(&self) -> Option<char> {
+        let mut ahead = self.chars.clone();
+        ahead.next();
+        ahead.next()
+    }
+
     fn skip_whitespace(&mut self) {
         while let Some(c) = self.peek() {
             if c.is_whitespace() {
-                if *c == '\n' {
-                    self.line += 1;
-                    self.column = 1;
-                }
                 self.advance();
             } else {
                 break;
@@ -128,6 +189,8 @@ impl<'a> Lexer<'a> {
                     _ => TokenType::Identifier(ident),
                 }
             }
+            "let" => TokenType::Let,
+            "const" => TokenType::Const,
             "fn" => TokenType::Fn,
             "return" => TokenType::Return,
             "if" => TokenType::If,
@@ -136,9 +199,20 @@ impl<'a> Lexer<'a> {
             "endif" => TokenType::EndIf,
             "repeat" => TokenType::Repeat,
             "endrepeat" => TokenType::EndRepeat,
+            "while" => TokenType::While,
+            "for" => TokenType::For,
+            "in" => TokenType::In,
             "break" => TokenType::Break,
             "continue" => TokenType::Continue,
+            "try" => TokenType::Try,
+            "catch" => TokenType::Catch,
+            "finally" => TokenType::Finally,
+            "throw" => TokenType::Throw,
             "print" => TokenType::Print,
+            "true" => TokenType::True,
+            "false" => TokenType::False,
+            "and" => TokenType::And,
+            "or" => TokenType::Or,
             "network" => {
                 self.skip_whitespace();
                 if let Some('.') = self.peek() {
@@ -166,8 +240,10 @@ impl<'a> Lexer<'a> {
                 if let Some('.') = self.peek() {
                     self.advance();
                     let next_word = self.read_next_word();
-                    if next_word == "now" {
-                        return TokenType::TimeNow;
+                    match next_word.as_str() {
+                        "now" => return TokenType::TimeNow,
+                        "monotonic" => return TokenType::TimeMonotonic,
+                        _ => {}
                     }
                 }
                 TokenType::Identifier(ident)
@@ -189,15 +265,88 @@ impl<'a> Lexer<'a> {
         word
     }
 
+    /// The body of a string after its opening quote, or after the `}` closing an interpolation.
     fn read_string(&mut self) -> TokenType {
         let mut s = String::new();
-        while let Some(c) = self.advance() {
-            if c == '"' {
-                break;
+        // Keep scanning to the closing quote after a bad escape so lexing resumes in the right place
+        let mut error = None;
+        loop {
+            match self.advance() {
+                None => return TokenType::Error("Unterminated string".to_string()),
+                Some('"') => break,
+                Some('$') if self.peek() == Some(&'{') => {
+                    self.advance();
+                    self.interpolations.push(0);
+                    if let Some(message) = error {
+                        return TokenType::Error(message);
+                    }
+                    return TokenType::StringPart(s);
+                }
+                Some('\\') => match self.read_escape() {
+                    Ok(c) => s.push(c),
+                    Err(message) => {
+                        error.get_or_insert(message);
+                    }
+                },
+                Some(c) => s.push(c),
+            }
+        }
+        match error {
+            Some(message) => TokenType::Error(message),
+            None => TokenType::String(s),
+        }
+    }
+
+    fn read_escape(&mut self) -> Result<char, String> {
+        match self.advance() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('0') => Ok('\0'),
+            Some('\\') => Ok('\\'),
+            Some('"') => Ok('"'),
+            Some('$') => Ok('$'),
+            Some('u') => {
+                if self.peek() != Some(&'{') {
+                    return Err("Expected '{' after \\u".to_string());
+                }
+                self.advance();
+                let mut hex = String::new();
+                while let Some(c) = self.peek().copied() {
+                    self.advance();
+                    if c == '}' {
+                        break;
+                    }
+                    hex.push(c);
+                }
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("Invalid unicode escape '\\u{{{}}}'", hex))
+            }
+            Some(c) => Err(format!("Unknown escape sequence '\\{}'", c)),
+            None => Err("Unterminated string".to_string()),
+        }
+    }
+
+    /// A `"""` string: taken verbatim across lines, no escapes or interpolation.
+    /// A newline right after the opening quotes is dropped.
+    fn read_raw_string(&mut self) -> TokenType {
+        if self.peek() == Some(&'\n') {
+            self.advance();
+        }
+        let mut s = String::new();
+        loop {
+            match self.advance() {
+                None => return TokenType::Error("Unterminated raw string".to_string()),
+                Some('"') if self.peek() == Some(&'"') && self.peek_next() == Some('"') => {
+                    self.advance();
+                    self.advance();
+                    return TokenType::String(s);
+                }
+                Some(c) => s.push(c),
             }
-            s.push(c);
         }
-        TokenType::String(s)
     }
 
     fn read_number(&mut self, first: char) -> TokenType {
@@ -205,9 +354,9 @@ impl<'a> Lexer<'a> {
         let mut is_float = false;
 
         while let Some(c) = self.peek() {
-            if c.is_digit(10) {
+            if c.is_ascii_digit() {
                 num.push(self.advance().unwrap());
-            } else if *c == '.' && !is_float {
+            } else if *c == '.' && !is_float && self.peek_next().is_some_and(|c| c.is_ascii_digit()) {
                 is_float = true;
                 num.push(self.advance().unwrap());
             } else {
@@ -222,72 +371,231 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Skips whitespace and comments, stopping at a `///` doc comment.
+    /// Returns an error token for an unterminated block comment.
+    fn skip_trivia(&mut self) -> Option<Token> {
+        loop {
+            self.skip_whitespace();
+            match (self.peek().copied(), self.peek_next()) {
+                (Some('/'), Some('/')) => {
+                    if self.is_doc_comment() {
+                        return None;
+                    }
+                    while self.peek().is_some_and(|c| *c != '\n') {
+                        self.advance();
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    let start = self.mark();
+                    self.advance();
+                    self.advance();
+                    // Block comments nest
+                    let mut depth = 1;
+                    while depth > 0 {
+                        match self.advance() {
+                            None => {
+                                let kind = TokenType::Error("Unterminated block comment".to_string());
+                                return Some(Token { kind, span: self.span_from(start) });
+                            }
+                            Some('/') if self.peek() == Some(&'*') => {
+                                self.advance();
+                                depth += 1;
+                            }
+                            Some('*') if self.peek() == Some(&'/') => {
+                                self.advance();
+                                depth -= 1;
+                            }
+                            Some(_) => {}
+                        }
+                    }
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// `///` but not `////`, which is an ordinary comment.
+    fn is_doc_comment(&self) -> bool {
+        let mut ahead = self.chars.clone();
+        ahead.next() == Some('/') && ahead.next() == Some('/') && ahead.next() == Some('/') && ahead.next() != Some('/')
+    }
+
+    fn read_doc_comment(&mut self) -> TokenType {
+        for _ in 0..3 {
+            self.advance();
+        }
+        let mut text = String::new();
+        while let Some(c) = self.peek().copied() {
+            if c == '\n' {
+                break;
+            }
+            text.push(c);
+            self.advance();
+        }
+        let text = text.strip_prefix(' ').unwrap_or(&text).trim_end();
+        TokenType::DocComment(text.to_string())
+    }
+
     pub fn next_token(&mut self) -> Token {
-        self.skip_whitespace();
+        if let Some(error) = self.skip_trivia() {
+            return error;
+        }
 
-        let line = self.line;
-        let column = self.column;
+        let start = self.mark();
+        if self.is_doc_comment() {
+            return Token { kind: self.read_doc_comment(), span: self.span_from(start) };
+        }
 
         if let Some(c) = self.advance() {
             match c {
-                '"' => Token { kind: self.read_string(), line, column },
-                '0'..='9' => Token { kind: self.read_number(c), line, column },
-                'a'..='z' | 'A'..='Z' | '_' => Token { kind: self.read_identifier(c), line, column },
+                '"' if self.peek() == Some(&'"') && self.peek_next() == Some('"') => {
+                    self.advance();
+                    self.advance();
+                    Token { kind: self.read_raw_string(), span: self.span_from(start) }
+                }
+                '"' => Token { kind: self.read_string(), span: self.span_from(start) },
+                '0'..='9' => Token { kind: self.read_number(c), span: self.span_from(start) },
+                'a'..='z' | 'A'..='Z' | '_' => Token { kind: self.read_identifier(c), span: self.span_from(start) },
 
-                '+' => Token { kind: TokenType::Plus, line, column },
-                '-' => Token { kind: TokenType::Minus, line, column },
-                '*' => Token { kind: TokenType::Star, line, column },
-                '/' => Token { kind: TokenType::Slash, line, column },
+                '+' | '-' | '*' | '/' if self.peek() == Some(&'=') => {
+                    self.advance();
+                    let kind = match c {
+                        '+' => TokenType::PlusAssign,
+                        '-' => TokenType::MinusAssign,
+                        '*' => TokenType::StarAssign,
+                        _ => TokenType::SlashAssign,
+                    };
+                    Token { kind, span: self.span_from(start) }
+                }
+                '+' => Token { kind: TokenType::Plus, span: self.span_from(start) },
+                '-' => Token { kind: TokenType::Minus, span: self.span_from(start) },
+                '*' => Token { kind: TokenType::Star, span: self.span_from(start) },
+                '/' => Token { kind: TokenType::Slash, span: self.span_from(start) },
+                '%' => Token { kind: TokenType::Percent, span: self.span_from(start) },
 
                 '=' => {
                     if let Some('=') = self.peek() {
                         self.advance();
-                        Token { kind: TokenType::EqualEqual, line, column }
+                        Token { kind: TokenType::EqualEqual, span: self.span_from(start) }
                     } else {
-                        Token { kind: TokenType::Assign, line, column }
+                        Token { kind: TokenType::Assign, span: self.span_from(start) }
                     }
                 }
 
                 '!' => {
                     if let Some('=') = self.peek() {
                         self.advance();
-                        Token { kind: TokenType::NotEqual, line, column }
+                        Token { kind: TokenType::NotEqual, span: self.span_from(start) }
                     } else {
-                        Token { kind: TokenType::Identifier("!".to_string()), line, column }
+                        Token { kind: TokenType::Bang, span: self.span_from(start) }
                     }
                 }
 
                 '>' => {
                     if let Some('=') = self.peek() {
                         self.advance();
-                        Token { kind: TokenType::GreaterEqual, line, column }
+                        Token { kind: TokenType::GreaterEqual, span: self.span_from(start) }
                     } else {
-                        Token { kind: TokenType::Greater, line, column }
+                        Token { kind: TokenType::Greater, span: self.span_from(start) }
                     }
                 }
 
                 '<' => {
                     if let Some('=') = self.peek() {
                         self.advance();
-                        Token { kind: TokenType::LessEqual, line, column }
+                        Token { kind: TokenType::LessEqual, span: self.span_from(start) }
                     } else {
-                        Token { kind: TokenType::Less, line, column }
+                        Token { kind: TokenType::Less, span: self.span_from(start) }
                     }
                 }
 
-                '(' => Token { kind: TokenType::LParen, line, column },
-                ')' => Token { kind: TokenType::RParen, line, column },
-                '{' => Token { kind: TokenType::LBrace, line, column },
-                '}' => Token { kind: TokenType::RBrace, line, column },
-                '[' => Token { kind: TokenType::LBracket, line, column },
-                ']' => Token { kind: TokenType::RBracket, line, column },
-                ',' => Token { kind: TokenType::Comma, line, column },
-                ':' => Token { kind: TokenType::Colon, line, column },
+                '(' => Token { kind: TokenType::LParen, span: self.span_from(start) },
+                ')' => Token { kind: TokenType::RParen, span: self.span_from(start) },
+                '{' => {
+                    if let Some(depth) = self.interpolations.last_mut() {
+                        *depth += 1;
+                    }
+                    Token { kind: TokenType::LBrace, span: self.span_from(start) }
+                }
+                // Closes an interpolation: carry on with the rest of the string
+                '}' if self.interpolations.last() == Some(&0) => {
+                    self.interpolations.pop();
+                    Token { kind: self.read_string(), span: self.span_from(start) }
+                }
+                '}' => {
+                    if let Some(depth) = self.interpolations.last_mut() {
+                        *depth -= 1;
+                    }
+                    Token { kind: TokenType::RBrace, span: self.span_from(start) }
+                }
+                '[' => Token { kind: TokenType::LBracket, span: self.span_from(start) },
+                ']' => Token { kind: TokenType::RBracket, span: self.span_from(start) },
+                ',' => Token { kind: TokenType::Comma, span: self.span_from(start) },
+                '.' if self.peek() == Some(&'.') => {
+                    self.advance();
+                    Token { kind: TokenType::DotDot, span: self.span_from(start) }
+                }
+                '.' => Token { kind: TokenType::Dot, span: self.span_from(start) },
+                ':' => Token { kind: TokenType::Colon, span: self.span_from(start) },
 
-                _ => Token { kind: TokenType::Identifier(c.to_string()), line, column },
+                _ => Token { kind: TokenType::Error(format!("Unexpected character '{}'", c)), span: self.span_from(start) },
             }
         } else {
-            Token { kind: TokenType::Eof, line, column }
+            Token { kind: TokenType::Eof, span: self.span_from(start) }
         }
     }
-                        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every token kind up to the end of input.
+    fn kinds(source: &str) -> Vec<TokenType> {
+        let mut lexer = Lexer::new(source);
+        std::iter::from_fn(|| match lexer.next_token().kind {
+            TokenType::Eof => None,
+            kind => Some(kind),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn unexpected_characters_are_errors() {
+        assert_eq!(kinds("let x = @").last(), Some(&TokenType::Error("Unexpected character '@'".to_string())));
+    }
+
+    #[test]
+    fn parser_reports_unexpected_characters() {
+        let diagnostics = crate::parser::Parser::new(Lexer::new("let x = 1 $ 2")).parse().expect_err("rejected");
+        assert!(diagnostics[0].to_string().contains("Unexpected character '$'"));
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        let source = "1 // line comment\n/* block /* nested */ still a comment */ 2\n//// not a doc comment\n3";
+        assert_eq!(kinds(source), [TokenType::Number(1), TokenType::Number(2), TokenType::Number(3)]);
+        assert_eq!(kinds("1 /* never closed /* */"), [TokenType::Number(1), TokenType::Error("Unterminated block comment".to_string())]);
+    }
+
+    #[test]
+    fn doc_comments_are_tokens() {
+        assert_eq!(
+            kinds("///  Adds one.  \nfn"),
+            [TokenType::DocComment(" Adds one.".to_string()), TokenType::Fn]
+        );
+    }
+
+    #[test]
+    fn tokens_carry_byte_offsets_and_columns() {
+        // Offsets count bytes, columns count characters
+        let mut lexer = Lexer::new("let é = 1\n  \"ab\"");
+        let spans: Vec<(usize, usize, usize, usize, usize, usize)> = std::iter::from_fn(|| {
+            let token = lexer.next_token();
+            let span = token.span;
+            (token.kind != TokenType::Eof).then_some((span.start, span.end, span.line, span.column, span.end_line, span.end_column))
+        })
+        .collect();
+        assert_eq!(spans, [(0, 3, 1, 1, 1, 4), (4, 6, 1, 5, 1, 6), (7, 8, 1, 7, 1, 8), (9, 10, 1, 9, 1, 10), (13, 17, 2, 3, 2, 7)]);
+    }
+}
@@ -1,2 +1,20 @@
 // src/lib.rs - FalconCore Library
 pub mod lexer;
+pub mod methods;
+pub mod natives;
+pub mod diagnostic;
+pub mod span;
+pub mod error;
+pub mod parser;
+pub mod resolver;
+pub mod compiler;
+pub mod chunk;
+pub mod bytecode;
+pub mod verifier;
+pub mod optimizer;
+pub mod compiler_aot;
+pub mod value;
+pub mod gc;
+pub mod vm;
+pub mod network;
+pub mod repl;
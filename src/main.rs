@@ -1,322 +1,240 @@
-mod lexer;
-mod parser;
-mod compiler;
-mod vm;
-mod network;
-
-use lexer::Lexer;
-use parser::Parser;
-use compiler::Compiler;
-use vm::VM;
-use network::NetworkStack;
-
-fn main() {
-    println!("FalconCore v0.1 - Full Pipeline + Advanced VM + Network Test");
-
-    let code = r#"
-        secure let x = 10
-        secure let y = 5
-        print x + y
-
-        repeat 3 {
-            print "Loop iteration"
+// src/main.rs - FalconCore CLI
+use falconcore::bytecode;
+use falconcore::compiler::{Compiler, Program};
+use falconcore::compiler_aot::compile_to_executable;
+use falconcore::gc::HeapConfig;
+use falconcore::lexer::{Lexer, TokenType};
+use falconcore::optimizer::{self, OptLevel};
+use falconcore::parser::{Expr, Parser};
+use falconcore::repl::start_repl;
+use falconcore::resolver::Resolver;
+use falconcore::span::Spanned;
+use falconcore::vm::VM;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+
+const USAGE: &str = "FalconCore v0.1
+
+Usage:
+  falconcore run <file>                 Run a .falcon script or precompiled .fbc file
+  falconcore repl                       Start the interactive REPL
+  falconcore tokens <file>              Dump the lexer token stream
+  falconcore ast <file>                 Dump the parsed AST
+  falconcore disasm <file>              Dump the compiled bytecode
+  falconcore compile <file> [-o <out>] [--strip]
+                                        Compile a script to a .fbc bytecode file
+                                        (--strip leaves out function names)
+  falconcore build <file> [-o <out>]    Compile a script to a native executable (AOT)
+  falconcore <file>                     Shorthand for `run <file>`
+
+Options:
+  -O0, -O1, -O2                         Bytecode optimization level for run, disasm and
+                                        compile (default -O1)
+  --max-heap=<size>                     Fail with a MemoryError once live heap data passes
+                                        <size> bytes (K, M and G suffixes work)";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut level = OptLevel::default();
+    let mut heap = HeapConfig::default();
+    let mut rest = vec![];
+    for arg in &args {
+        if let Some(flag) = OptLevel::from_flag(arg) {
+            level = flag;
+        } else if let Some(size) = arg.strip_prefix("--max-heap=") {
+            let Some(bytes) = parse_size(size) else {
+                eprintln!("error: invalid heap size '{}'\n\n{}", size, USAGE);
+                return ExitCode::from(2);
+            };
+            heap.max_bytes = Some(bytes);
+        } else {
+            rest.push(arg.as_str());
         }
+    }
+    let args = rest;
 
-        fn add(a, b) {
-            return a + b
+    let result = match args.as_slice() {
+        ["repl"] => {
+            start_repl();
+            Ok(())
         }
+        ["run", file] => run(file, level, heap),
+        ["tokens", file] => tokens(file),
+        ["ast", file] => ast(file),
+        ["disasm", file] => disasm(file, level),
+        ["compile", file, options @ ..] => compile_bytecode(file, options, level),
+        ["build", file] => build(file, None),
+        ["build", file, "-o", out] => build(file, Some(out)),
+        ["help" | "--help" | "-h"] => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        [file] if file.ends_with(".falcon") || file.ends_with(".fbc") => run(file, level, heap),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
 
-        secure let sum = add(20, 30)
-        print sum
-
-        secure let subnet = "192.168.1"
-        secure let devices = network.scan subnet
-        print "Found " + devices.length + " devices"
-    "#;
-
-    // Lexer → Parser → Compiler
-    let lexer = Lexer::new(code);
-    let mut parser = Parser::new(lexer);
-    let ast = parser.parse();
-
-    let mut compiler = Compiler::new();
-    compiler.compile(ast);
-
-    // VM
-    let mut vm = VM::new(compiler.get_constants().clone(), compiler.get_code().clone());
-    vm.run();
-
-    // Network test
-    let net = NetworkStack::new();
-    let devices = net.scan("192.168.1");
-    println!("\nNetwork Scan Result: {} devices found", devices.len());
-    for ip in devices {
-        println!(" - {} (MAC: {})", ip, net.get_mac(&ip));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
     }
-}mod lexer;
-mod parser;
-mod compiler;
-mod vm;
-mod network;
-
-use lexer::Lexer;
-use parser::Parser;
-use compiler::Compiler;
-use vm::VM;
-use network::NetworkStack;
+}
 
-fn main() {
-    println!("FalconCore v0.1 - Full Pipeline + Network Stack Test");
+/// `4096`, `64K`, `512M` or `2G`.
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, unit) = match size.char_indices().last()? {
+        (at, 'K' | 'k') => (&size[..at], 1 << 10),
+        (at, 'M' | 'm') => (&size[..at], 1 << 20),
+        (at, 'G' | 'g') => (&size[..at], 1 << 30),
+        _ => (size, 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(unit)
+}
 
-    let code = r#"
-        secure let subnet = "192.168.1"
-        secure let devices = network.scan subnet
-        print "Found " + devices.length + " devices"
-        print "Hello from FalconCore VM!"
-    "#;
+fn read_source(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("cannot read '{}': {}", path, e))
+}
 
-    let lexer = Lexer::new(code);
-    let mut parser = Parser::new(lexer);
-    let ast = parser.parse();
+fn parse(source: &str, path: &str) -> Result<Vec<Spanned<Expr>>, String> {
+    let lexer = Lexer::new(source);
+    let mut parser = Parser::new(lexer).with_file(path);
+    parser.parse().map_err(|diagnostics| {
+        for diagnostic in &diagnostics {
+            eprintln!("{}\n", diagnostic);
+        }
+        format!("could not parse '{}' due to {} syntax error(s)", path, diagnostics.len())
+    })
+}
 
-    println!("\nAST:");
-    for node in &ast {
-        println!("{:#?}", node);
-    }
+/// Parses and resolves a script, so scope and const errors are reported before compiling.
+fn analyze(source: &str, path: &str) -> Result<Vec<Spanned<Expr>>, String> {
+    let ast = parse(source, path)?;
+    let mut resolver = Resolver::new(source).with_file(path);
+    resolver.resolve(&ast).map_err(|diagnostics| {
+        for diagnostic in &diagnostics {
+            eprintln!("{}\n", diagnostic);
+        }
+        format!("could not compile '{}' due to {} error(s)", path, diagnostics.len())
+    })?;
+    Ok(ast)
+}
 
+fn compile(source: &str, path: &str, level: OptLevel) -> Result<Program, String> {
     let mut compiler = Compiler::new();
-    compiler.compile(ast);
-
-    println!("\nBytecode:");
-    for (i, op) in compiler.get_code().iter().enumerate() {
-        println!("{:03}: {:?}", i, op);
-    }
-
-    let mut vm = VM::new(compiler.get_constants().clone(), compiler.get_code().clone());
-    vm.run();
-
-    // Network test
-    let net = NetworkStack::new();
-    let devices = net.scan("192.168.1");
-    println!("\nNetwork Scan Result (placeholder): {} devices", devices.len());
+    compiler.compile(analyze(source, path)?);
+    Ok(optimizer::optimize(compiler.program(), level))
 }
-use falconcore::lexer::{Lexer, TokenType};
-
-fn main() {
-    println!("FalconCore v0.1 - Lexer Test");
 
-    let code = r#"
-    secure let x = 42
-    print "Hello from FalconCore!"
-    if x > 10 {
-        print "x is greater than 10"
-    }
-    "#;
+fn run(path: &str, level: OptLevel, heap: HeapConfig) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|e| format!("cannot read '{}': {}", path, e))?;
+
+    // Precompiled bytecode skips straight to the VM
+    let vm = if bytes.starts_with(&bytecode::MAGIC) {
+        VM::load(&bytes).map_err(|e| format!("cannot load '{}': {}", path, e))?
+    } else {
+        let source = String::from_utf8(bytes).map_err(|_| format!("cannot read '{}': not valid UTF-8", path))?;
+        VM::new(compile(&source, path, level)?)
+    };
+    let mut vm = vm.with_heap_config(heap);
+    vm.run().map_err(|e| e.to_string())?;
+    Ok(())
+}
 
-    let mut lexer = Lexer::new(code);
+fn tokens(path: &str) -> Result<(), String> {
+    let source = read_source(path)?;
+    let mut lexer = Lexer::new(&source);
     loop {
         let token = lexer.next_token();
-        println!("{:?}", token);
+        println!("{:4}:{:<3} {:?}", token.span.line, token.span.column, token.kind);
         if token.kind == TokenType::Eof {
             break;
         }
     }
-
-    println!("Lexer test complete!");
-}
-mod lexer;
-mod parser;
-
-use lexer::Lexer;
-use parser::Parser;
-
-fn main() {
-    println!("FalconCore v0.1 - Lexer + Parser Test");
-
-    let code = r#"
-        secure let x = 42
-        secure let y = "Hello from FalconCore!"
-        print x + 8
-        print y
-    "#;
-
-    let lexer = Lexer::new(code);
-    let mut parser = Parser::new(lexer);
-
-    let ast = parser.parse();
-
-    println!("AST:");
-    for node in ast {
-        println!("{:?}", node);
-    }
-
-    println!("Parsing complete!");
+    Ok(())
 }
-mod lexer;
-mod parser;
-
-use lexer::Lexer;
-use parser::Parser;
-
-fn main() {
-    println!("FalconCore v0.1 - Lexer + Parser Test");
-
-    let code = r#"
-        secure let x = 42
-        secure const pi = 3.14
-        print "Hello from FalconCore!"
-        if x > 10 {
-            print "x is greater than 10"
-        } else {
-            print "x is small"
-        }
-    "#;
-
-    let lexer = Lexer::new(code);
-    let mut parser = Parser::new(lexer);
-
-    let ast = parser.parse();
 
-    println!("AST:");
-    for node in ast {
+fn ast(path: &str) -> Result<(), String> {
+    let source = read_source(path)?;
+    for node in parse(&source, path)? {
         println!("{:#?}", node);
     }
-
-    println!("Parsing complete!");
+    Ok(())
 }
-mod lexer;
-mod parser;
-mod compiler;
-
-use lexer::Lexer;
-use parser::Parser;
-use compiler::Compiler;
-
-fn main() {
-    println!("FalconCore v0.1 - Full Pipeline Test");
-
-    let code = r#"
-        secure let x = 42
-        secure let y = 8
-        print x + y
-    "#;
-
-    // Lexer
-    let lexer = Lexer::new(code);
 
-    // Parser
-    let mut parser = Parser::new(lexer);
-    let ast = parser.parse();
-
-    println!("AST:");
-    for node in &ast {
-        println!("{:#?}", node);
-    }
-
-    // Compiler
-    let mut compiler = Compiler::new();
-    compiler.compile(ast);
+fn disasm(path: &str, level: OptLevel) -> Result<(), String> {
+    let source = read_source(path)?;
+    let program = compile(&source, path, level)?;
 
-    println!("\nGenerated Bytecode:");
-    for (i, op) in compiler.get_code().iter().enumerate() {
+    println!("Bytecode:");
+    for (i, op) in program.code.iter().enumerate() {
         println!("{:03}: {:?}", i, op);
     }
 
     println!("\nConstants:");
-    for (i, const_val) in compiler.get_constants().iter().enumerate() {
+    for (i, const_val) in program.constants.iter().enumerate() {
         println!("{:03}: {:?}", i, const_val);
     }
-}
-mod lexer;
-mod parser;
-mod compiler;
-mod vm;
-
-use lexer::Lexer;
-use parser::Parser;
-use compiler::Compiler;
-use vm::VM;
-
-fn main() {
-    println!("FalconCore v0.1 - Full Pipeline: Lexer → Parser → Compiler → VM");
-
-    let code = r#"
-        secure let x = 42
-        secure let y = 8
-        print x + y
-        print "Hello from FalconCore VM!"
-    "#;
-
-    // Lexer
-    let lexer = Lexer::new(code);
 
-    // Parser
-    let mut parser = Parser::new(lexer);
-    let ast = parser.parse();
-
-    println!("\nAST:");
-    for node in &ast {
-        println!("{:#?}", node);
+    println!("\nGlobals:");
+    for (i, name) in program.globals.iter().enumerate() {
+        println!("{:03}: {}", i, name);
     }
+    Ok(())
+}
 
-    // Compiler
-    let mut compiler = Compiler::new();
-    compiler.compile(ast);
-
-    println!("\nGenerated Bytecode:");
-    for (i, op) in compiler.get_code().iter().enumerate() {
-        println!("{:03}: {:?}", i, op);
+fn compile_bytecode(path: &str, options: &[&str], level: OptLevel) -> Result<(), String> {
+    let mut out = None;
+    let mut strip = false;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match *option {
+            "-o" => out = Some(*options.next().ok_or("-o needs an output file")?),
+            "--strip" => strip = true,
+            other => return Err(format!("unknown option '{}'\n\n{}", other, USAGE)),
+        }
     }
 
-    println!("\nConstants:");
-    for (i, const_val) in compiler.get_constants().iter().enumerate() {
-        println!("{:03}: {:?}", i, const_val);
-    }
+    let source = read_source(path)?;
+    let program = compile(&source, path, level)?;
+    let out = match out {
+        Some(out) => PathBuf::from(out),
+        None => Path::new(path).with_extension("fbc"),
+    };
+    fs::write(&out, bytecode::encode(&program, !strip)).map_err(|e| format!("cannot write '{}': {}", out.display(), e))?;
 
-    // VM
-    let mut vm = VM::new(compiler.get_constants().clone(), compiler.get_code().clone());
-    vm.run();
+    println!("Bytecode written: {}", out.display());
+    Ok(())
 }
-// Network test
-let net = NetworkStack::new();
-let devices = net.scan("192.168.1");
-println!("Network Scan Result: {} devices found", devices.len());
-for ip in devices {
-    println!(" - {}", ip);
-}
-mod lexer;
-mod parser;
-mod compiler;
-mod vm;
-mod network;
-mod repl;
-
-use repl::start_repl;
 
-fn main() {
-    println!("FalconCore v0.1 - REPL Mode");
-    start_repl();
+fn build(path: &str, out: Option<&str>) -> Result<(), String> {
+    let source = read_source(path)?;
+    let binary = compile_to_executable(&analyze(&source, path)?)?;
+
+    let out = match out {
+        Some(out) => PathBuf::from(out),
+        None => PathBuf::from(Path::new(path).file_stem().ok_or("invalid input file name")?),
+    };
+    let object = out.with_extension("o");
+    fs::write(&object, binary).map_err(|e| format!("cannot write '{}': {}", object.display(), e))?;
+
+    let status = Command::new("cc")
+        .arg(&object)
+        .arg("-o")
+        .arg(&out)
+        .status()
+        .map_err(|e| format!("cannot run linker 'cc': {} (object file left at '{}')", e, object.display()))?;
+    if !status.success() {
+        return Err(format!("linking failed (object file left at '{}')", object.display()));
     }
-if std::env::args().any(|arg| arg == "--jit") {
-    // JIT mode
-    jit_compile(&compiler.get_code()).unwrap();
-}
-if std::env::args().any(|arg| arg == "--aot") {
-    // AOT compile example
-    let ast = /* parse your code */;
-    let binary = compile_to_executable(&ast).unwrap();
-    std::fs::write("falconcore_binary", binary).unwrap();
-    println!("AOT binary created: falconcore_binary");
-} else {
-    start_repl();
-}
-if std::env::args().any(|arg| arg == "--jit") {
-    println!("JIT test: looping 10000 times");
-    jit_test_loop().unwrap();
-    return;
+    let _ = fs::remove_file(&object);
+
+    println!("AOT binary created: {}", out.display());
+    Ok(())
 }
-let code = r#"
-    secure let subnet = "192.168.1"
-    secure let devices = network.scan subnet
-    print "Found " + devices.length + " devices"
-"#;
@@ -0,0 +1,160 @@
+// src/methods.rs - FalconCore built-in properties and methods on lists, maps and strings
+use crate::error::{ErrorKind, RuntimeError};
+use crate::gc;
+use crate::value::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+/// `value.name` without a call.
+pub fn get_property(object: &Value, name: &str) -> Result<Value, RuntimeError> {
+    // Map entries read like fields, so `e.kind` works on a caught error
+    if let Value::Map(entries) = object {
+        if let Some(value) = entries.borrow().get(name) {
+            return Ok(value.clone());
+        }
+    }
+    match (object, name) {
+        (_, "length") => length(object),
+        _ => Err(RuntimeError::new(
+            ErrorKind::Undefined,
+            format!("Unknown member '{}' on {}", name, object.type_name()),
+        )),
+    }
+}
+
+/// `value.name(args...)`. Takes the receiver as pushed: new text computed from a secret receiver
+/// or argument goes straight into a secret, like the string natives' results.
+pub fn invoke(object: &Value, name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    let secret = object.is_secret() || args.iter().any(Value::is_secret);
+    let object = object.expose();
+    match (object, name) {
+        (_, "length") => {
+            expect_args(name, args, 0)?;
+            length(object)
+        }
+        (Value::List(items), "push") => {
+            expect_args(name, args, 1)?;
+            items.borrow_mut().push(args[0].clone());
+            gc::grow(mem::size_of::<Value>());
+            Ok(Value::Nil)
+        }
+        (Value::List(items), "pop") => {
+            expect_args(name, args, 0)?;
+            items.borrow_mut().pop().ok_or_else(|| RuntimeError::new(ErrorKind::Index, "pop() on an empty list"))
+        }
+        (Value::Map(entries), "keys") => {
+            expect_args(name, args, 0)?;
+            let entries = entries.borrow();
+            let mut keys: Vec<&String> = entries.keys().collect();
+            keys.sort();
+            // Keys of a secret map stay off the tracked heap, so the secret list can wipe them
+            let key = |key: &String| if secret { Value::Str(Rc::from(key.as_str())) } else { Value::string(key) };
+            Ok(list(keys.into_iter().map(key).collect()))
+        }
+        (Value::List(items), "contains") => {
+            expect_args(name, args, 1)?;
+            let needle = args[0].expose();
+            Ok(Value::Bool(items.borrow().iter().any(|item| item.expose() == needle)))
+        }
+        (Value::Map(entries), "contains") => {
+            expect_args(name, args, 1)?;
+            let key = key(&args[0])?;
+            Ok(Value::Bool(entries.borrow().contains_key(key)))
+        }
+        (Value::Str(s), "contains") => {
+            expect_args(name, args, 1)?;
+            match args[0].expose() {
+                Value::Str(needle) => Ok(Value::Bool(s.contains(&**needle))),
+                other => Err(RuntimeError::new(
+                    ErrorKind::Type,
+                    format!("contains() on a string expects a string, got {}", other.type_name()),
+                )),
+            }
+        }
+        (Value::List(items), "slice") => {
+            expect_args(name, args, 2)?;
+            let items = items.borrow();
+            let (start, end) = slice_bounds(args, items.len())?;
+            Ok(list(items[start..end].to_vec()))
+        }
+        (Value::Str(s), "slice") => {
+            expect_args(name, args, 2)?;
+            let (start, end) = slice_bounds(args, s.chars().count())?;
+            let sliced: String = s.chars().skip(start).take(end - start).collect();
+            Ok(if secret { Value::secret_string(sliced) } else { Value::string(&sliced) })
+        }
+        _ => Err(RuntimeError::new(
+            ErrorKind::Undefined,
+            format!("Unknown method '{}' on {}", name, object.type_name()),
+        )),
+    }
+}
+
+pub fn list(items: Vec<Value>) -> Value {
+    let items = Rc::new(RefCell::new(items));
+    gc::track_list(&items);
+    Value::List(items)
+}
+
+pub fn map(entries: HashMap<String, Value>) -> Value {
+    let entries = Rc::new(RefCell::new(entries));
+    gc::track_map(&entries);
+    Value::Map(entries)
+}
+
+/// Map keys are strings; a secret key is looked up by its plaintext.
+pub fn key(value: &Value) -> Result<&str, RuntimeError> {
+    match value.expose() {
+        Value::Str(s) => Ok(s),
+        other => Err(RuntimeError::new(ErrorKind::Type, format!("Map keys must be strings, got {}", other.type_name()))),
+    }
+}
+
+/// A key to store an entry under. Map keys always print in plaintext, so a secret one is refused
+/// rather than leaked.
+pub fn stored_key(value: &Value) -> Result<&str, RuntimeError> {
+    if value.is_secret() {
+        return Err(RuntimeError::new(ErrorKind::Type, "Map keys can't be secret"));
+    }
+    key(value)
+}
+
+fn length(object: &Value) -> Result<Value, RuntimeError> {
+    let len = match object {
+        Value::Str(s) => s.chars().count(),
+        Value::List(items) => items.borrow().len(),
+        Value::Map(entries) => entries.borrow().len(),
+        _ => return Err(RuntimeError::new(ErrorKind::Type, format!("{} has no length", object.type_name()))),
+    };
+    Ok(Value::Int(len as i64))
+}
+
+fn expect_args(name: &str, args: &[Value], arity: usize) -> Result<(), RuntimeError> {
+    if args.len() == arity {
+        Ok(())
+    } else {
+        Err(RuntimeError::new(
+            ErrorKind::Arity,
+            format!("{}() expects {} argument(s), got {}", name, arity, args.len()),
+        ))
+    }
+}
+
+/// `slice(start, end)` bounds, end exclusive and both clamped to `0..=len`.
+fn slice_bounds(args: &[Value], len: usize) -> Result<(usize, usize), RuntimeError> {
+    let mut bounds = [0; 2];
+    for (bound, arg) in bounds.iter_mut().zip(args) {
+        *bound = match arg.expose() {
+            Value::Int(n) => (*n).clamp(0, len as i64) as usize,
+            other => {
+                return Err(RuntimeError::new(
+                    ErrorKind::Type,
+                    format!("slice() bounds must be ints, got {}", other.type_name()),
+                ))
+            }
+        };
+    }
+    Ok((bounds[0], bounds[1].max(bounds[0])))
+}
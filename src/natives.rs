@@ -0,0 +1,356 @@
+// src/natives.rs - FalconCore built-in native functions
+use crate::error::{ErrorKind, RuntimeError};
+use crate::gc;
+use crate::methods;
+use crate::network::NetworkStack;
+use crate::value::{NativeFunction, Value};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub fn all() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction { name: "reveal", arity: 1, func: reveal },
+        NativeFunction { name: "crypto.random", arity: 2, func: crypto_random },
+        NativeFunction { name: "time.now", arity: 0, func: time_now },
+        NativeFunction { name: "time.monotonic", arity: 0, func: time_monotonic },
+        NativeFunction { name: "wait", arity: 1, func: wait },
+        NativeFunction { name: "network.scan", arity: 1, func: network_scan },
+        // Bare aliases of `string.char` and `string.ord`
+        NativeFunction { name: "char", arity: 1, func: string_char },
+        NativeFunction { name: "ord", arity: 1, func: string_ord },
+    ]
+}
+
+/// The `string` module: `string.upper(s)` and friends.
+pub fn string_module() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction { name: "string.len", arity: 1, func: string_len },
+        NativeFunction { name: "string.upper", arity: 1, func: string_upper },
+        NativeFunction { name: "string.lower", arity: 1, func: string_lower },
+        NativeFunction { name: "string.split", arity: 2, func: string_split },
+        NativeFunction { name: "string.trim", arity: 1, func: string_trim },
+        NativeFunction { name: "string.replace", arity: 3, func: string_replace },
+        NativeFunction { name: "string.contains", arity: 2, func: string_contains },
+        NativeFunction { name: "string.char", arity: 1, func: string_char },
+        NativeFunction { name: "string.ord", arity: 1, func: string_ord },
+    ]
+}
+
+/// The `gc` module: `gc.collect()` and `gc.stats()`.
+pub fn gc_module() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction { name: "gc.collect", arity: 0, func: gc_collect },
+        NativeFunction { name: "gc.stats", arity: 0, func: gc_stats },
+    ]
+}
+
+/// Every builtin global: the plain natives plus one map per module.
+pub fn globals() -> Vec<(&'static str, Value)> {
+    let mut globals: Vec<(&'static str, Value)> =
+        all().into_iter().map(|native| (native.name, Value::NativeFunction(native))).collect();
+    globals.push(("string", module(string_module())));
+    globals.push(("gc", module(gc_module())));
+    globals
+}
+
+/// A module value: a map from each function's short name to the function.
+fn module(functions: Vec<NativeFunction>) -> Value {
+    let entries = functions
+        .into_iter()
+        .map(|native| {
+            let short = native.name.rsplit('.').next().unwrap_or(native.name);
+            (short.to_string(), Value::NativeFunction(native))
+        })
+        .collect();
+    methods::map(entries)
+}
+
+/// The only way to get a secret's plaintext back out.
+fn reveal(args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(match &args[0] {
+        Value::Secret(secret) => secret.reveal(),
+        other => other.clone(),
+    })
+}
+
+/// Uniform integer in `min..=max` from the OS CSPRNG.
+fn crypto_random(args: &[Value]) -> Result<Value, RuntimeError> {
+    let min = int_arg("crypto.random", args, 0)?;
+    let max = int_arg("crypto.random", args, 1)?;
+    if min > max {
+        return Err(RuntimeError::new(
+            ErrorKind::Value,
+            // The arguments as passed, so secret bounds show as [REDACTED]
+            format!("crypto.random: min ({}) is greater than max ({})", args[0], args[1]),
+        ));
+    }
+
+    // Width of the range minus one, so the full i64 range doesn't overflow
+    let span = max.wrapping_sub(min) as u64;
+    if span == u64::MAX {
+        return Ok(Value::Int(random_u64()? as i64));
+    }
+
+    // Rejection sampling: drop the top partial bucket so every value is equally likely
+    let range = span + 1;
+    let zone = u64::MAX - (u64::MAX % range + 1) % range;
+    loop {
+        let n = random_u64()?;
+        if n <= zone {
+            return Ok(Value::Int(min.wrapping_add((n % range) as i64)));
+        }
+    }
+}
+
+/// Wall-clock time in milliseconds since the Unix epoch.
+fn time_now(_args: &[Value]) -> Result<Value, RuntimeError> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| RuntimeError::new(ErrorKind::Io, format!("time.now: system clock is before the epoch: {}", e)))?;
+    Ok(Value::Int(elapsed.as_millis() as i64))
+}
+
+/// Milliseconds on a clock that never goes backwards; only differences are meaningful.
+fn time_monotonic(_args: &[Value]) -> Result<Value, RuntimeError> {
+    static START: OnceLock<Instant> = OnceLock::new();
+    let start = START.get_or_init(Instant::now);
+    Ok(Value::Int(start.elapsed().as_millis() as i64))
+}
+
+fn wait(args: &[Value]) -> Result<Value, RuntimeError> {
+    let ms = int_arg("wait", args, 0)?;
+    if ms < 0 {
+        return Err(RuntimeError::new(ErrorKind::Value, format!("wait: duration must not be negative, got {}", args[0])));
+    }
+    thread::sleep(Duration::from_millis(ms as u64));
+    Ok(Value::Nil)
+}
+
+/// Ports probed on every host of the subnet.
+const SCAN_PORTS: [u16; 4] = [22, 80, 443, 8080];
+
+/// `network.scan "192.168.1"`: a list of `{"ip": ..., "mac": ..., "ports": [...]}` for hosts that answered.
+fn network_scan(args: &[Value]) -> Result<Value, RuntimeError> {
+    let subnet = match args[0].expose() {
+        Value::Str(s) => s.clone(),
+        other => {
+            return Err(RuntimeError::new(
+                ErrorKind::Type,
+                format!("network.scan: subnet must be a string, got {}", other.type_name()),
+            ))
+        }
+    };
+    let net = NetworkStack::new();
+    let devices = net
+        .scan(&subnet, &SCAN_PORTS)
+        .map_err(|message| RuntimeError::new(ErrorKind::Value, format!("network.scan: '{}' is {}", args[0], message)))?
+        .into_iter()
+        .map(|(ip, ports)| {
+            let mut device = HashMap::new();
+            device.insert("mac".to_string(), Value::string(&net.get_mac(&ip)));
+            device.insert("ip".to_string(), Value::string(&ip));
+            device.insert("ports".to_string(), methods::list(ports.into_iter().map(|p| Value::Int(p as i64)).collect()));
+            methods::map(device)
+        })
+        .collect();
+    Ok(methods::list(devices))
+}
+
+fn int_arg(name: &str, args: &[Value], idx: usize) -> Result<i64, RuntimeError> {
+    match args[idx].expose() {
+        Value::Int(n) => Ok(*n),
+        other => Err(RuntimeError::new(
+            ErrorKind::Type,
+            format!("{}: argument {} must be an int, got {}", name, idx + 1, other.type_name()),
+        )),
+    }
+}
+
+fn random_u64() -> Result<u64, RuntimeError> {
+    let mut bytes = [0u8; 8];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| RuntimeError::new(ErrorKind::Io, format!("crypto.random: OS random source failed: {}", e)))?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn str_arg<'v>(name: &str, args: &'v [Value], idx: usize) -> Result<&'v str, RuntimeError> {
+    match args[idx].expose() {
+        Value::Str(s) => Ok(s),
+        other => Err(RuntimeError::new(
+            ErrorKind::Type,
+            format!("{}: argument {} must be a string, got {}", name, idx + 1, other.type_name()),
+        )),
+    }
+}
+
+/// String functions computed from a secret argument return a secret.
+fn tainted(args: &[Value], result: Value) -> Value {
+    if args.iter().any(Value::is_secret) {
+        Value::secret(result)
+    } else {
+        result
+    }
+}
+
+/// Like `tainted`, for new text: from a secret argument it goes straight into the secret, so no
+/// tracked plaintext copy is made.
+fn tainted_text(args: &[Value], text: String) -> Value {
+    if args.iter().any(Value::is_secret) {
+        Value::secret_string(text)
+    } else {
+        Value::string(&text)
+    }
+}
+
+fn string_len(args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = str_arg("string.len", args, 0)?;
+    Ok(tainted(args, Value::Int(s.chars().count() as i64)))
+}
+
+fn string_upper(args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = str_arg("string.upper", args, 0)?;
+    Ok(tainted_text(args, s.to_uppercase()))
+}
+
+fn string_lower(args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = str_arg("string.lower", args, 0)?;
+    Ok(tainted_text(args, s.to_lowercase()))
+}
+
+fn string_split(args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = str_arg("string.split", args, 0)?;
+    let separator = str_arg("string.split", args, 1)?;
+    // Parts of a secret stay off the tracked heap, so the secret list can wipe them when it drops
+    let secret = args.iter().any(Value::is_secret);
+    let part = |part: &str| if secret { Value::Str(Rc::from(part)) } else { Value::string(part) };
+    let parts: Vec<Value> = if separator.is_empty() {
+        s.chars().map(|c| part(c.encode_utf8(&mut [0; 4]))).collect()
+    } else {
+        s.split(separator).map(part).collect()
+    };
+    Ok(tainted(args, methods::list(parts)))
+}
+
+fn string_trim(args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = str_arg("string.trim", args, 0)?;
+    Ok(tainted_text(args, s.trim().to_string()))
+}
+
+fn string_replace(args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = str_arg("string.replace", args, 0)?;
+    let from = str_arg("string.replace", args, 1)?;
+    let to = str_arg("string.replace", args, 2)?;
+    if from.is_empty() {
+        return Err(RuntimeError::new(ErrorKind::Value, "string.replace: pattern must not be empty"));
+    }
+    Ok(tainted_text(args, s.replace(from, to)))
+}
+
+fn string_contains(args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = str_arg("string.contains", args, 0)?;
+    let needle = str_arg("string.contains", args, 1)?;
+    Ok(tainted(args, Value::Bool(s.contains(needle))))
+}
+
+/// The one-character string for a Unicode code point.
+fn string_char(args: &[Value]) -> Result<Value, RuntimeError> {
+    let code = int_arg("string.char", args, 0)?;
+    let c = u32::try_from(code)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or_else(|| RuntimeError::new(ErrorKind::Value, format!("string.char: {} is not a valid code point", args[0])))?;
+    Ok(tainted_text(args, c.to_string()))
+}
+
+/// The code point of a one-character string.
+fn string_ord(args: &[Value]) -> Result<Value, RuntimeError> {
+    let s = str_arg("string.ord", args, 0)?;
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(tainted(args, Value::Int(c as i64))),
+        // A secret's length is secret too
+        _ if args[0].is_secret() => Err(RuntimeError::new(ErrorKind::Value, "string.ord: expected a single character")),
+        _ => Err(RuntimeError::new(
+            ErrorKind::Value,
+            format!("string.ord: expected a single character, got {} characters", s.chars().count()),
+        )),
+    }
+}
+
+/// Natives can't see the VM's roots, so this only asks: the VM collects before its next call or
+/// jump, which is before anything could observe the difference.
+fn gc_collect(_args: &[Value]) -> Result<Value, RuntimeError> {
+    gc::request();
+    Ok(Value::Nil)
+}
+
+/// Collector counters; the live figures are as of the last collection.
+fn gc_stats(_args: &[Value]) -> Result<Value, RuntimeError> {
+    let (stats, limit) = gc::stats().unwrap_or_default();
+    let count = |n: usize| Value::Int(n as i64);
+    Ok(methods::map(HashMap::from([
+        ("collections".to_string(), count(stats.collections)),
+        ("freed".to_string(), count(stats.freed)),
+        ("objects".to_string(), count(stats.live_objects)),
+        ("bytes".to_string(), count(stats.live_bytes)),
+        ("allocated".to_string(), count(stats.allocated)),
+        ("threshold".to_string(), count(stats.threshold)),
+        ("limit".to_string(), limit.map_or(Value::Nil, count)),
+    ])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(result: Result<Value, RuntimeError>) -> String {
+        result.expect_err("the call fails").message
+    }
+
+    #[test]
+    fn char_and_ord_round_trip() {
+        assert_eq!(string_char(&[Value::Int(65)]).unwrap(), Value::string("A"));
+        assert_eq!(string_ord(&[Value::string("A")]).unwrap(), Value::Int(65));
+        let secret = string_char(&[Value::secret(Value::Int(66))]).unwrap();
+        assert!(secret.is_secret());
+        assert_eq!(secret.expose(), &Value::string("B"));
+    }
+
+    #[test]
+    fn char_and_ord_errors_keep_secrets() {
+        assert_eq!(message(string_char(&[Value::Int(-1)])), "string.char: -1 is not a valid code point");
+        assert_eq!(
+            message(string_char(&[Value::secret(Value::Int(-1))])),
+            "string.char: [REDACTED] is not a valid code point"
+        );
+        assert_eq!(
+            message(string_ord(&[Value::string("abc")])),
+            "string.ord: expected a single character, got 3 characters"
+        );
+        assert_eq!(message(string_ord(&[Value::secret(Value::string("abc"))])), "string.ord: expected a single character");
+    }
+
+    #[test]
+    fn range_errors_keep_secrets() {
+        assert_eq!(message(wait(&[Value::Int(-5)])), "wait: duration must not be negative, got -5");
+        assert_eq!(
+            message(wait(&[Value::secret(Value::Int(-5))])),
+            "wait: duration must not be negative, got [REDACTED]"
+        );
+        assert_eq!(
+            message(crypto_random(&[Value::secret(Value::Int(100)), Value::Int(50)])),
+            "crypto.random: min ([REDACTED]) is greater than max (50)"
+        );
+    }
+
+    #[test]
+    fn bare_char_and_ord_are_globals() {
+        let globals = globals();
+        for name in ["char", "ord", "reveal", "string", "gc"] {
+            assert!(globals.iter().any(|(global, _)| *global == name), "{} is missing", name);
+        }
+    }
+}
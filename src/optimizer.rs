@@ -0,0 +1,579 @@
+// src/optimizer.rs - FalconCore bytecode optimizer (constant folding, dead code, jump threading)
+//
+// Works on the compiler's `Program` before it is verified. Rewrites happen in place against the
+// original instruction indices, with dropped instructions only marked; a final compaction
+// renumbers jump targets, handler entries and function entry points in one go. A jump to a
+// dropped instruction lands on the next one that survives.
+use crate::compiler::{Opcode, Program};
+use crate::value::{Function, Value};
+use crate::vm;
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+
+/// How hard to try, as picked with -O0/-O1/-O2 on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    /// Run the compiler's output as is.
+    O0,
+    /// Constant folding, unreachable code removal and a deduplicated constant pool.
+    #[default]
+    O1,
+    /// Everything in O1 plus jump threading and dead store/load pair removal.
+    O2,
+}
+
+impl OptLevel {
+    pub fn from_flag(flag: &str) -> Option<OptLevel> {
+        match flag {
+            "-O0" => Some(OptLevel::O0),
+            "-O1" => Some(OptLevel::O1),
+            "-O2" => Some(OptLevel::O2),
+            _ => None,
+        }
+    }
+}
+
+/// Optimizes a compiled program. The result behaves the same, errors included: anything that
+/// would fail at run time (division by zero, overflow, type errors) is left for the VM.
+pub fn optimize(program: Program, level: OptLevel) -> Program {
+    if level == OptLevel::O0 {
+        return program;
+    }
+    let mut pass = Pass::new(program);
+    pass.fold_constants();
+    if level >= OptLevel::O2 {
+        pass.thread_jumps();
+    }
+    pass.remove_unreachable();
+    if level >= OptLevel::O2 {
+        pass.remove_dead_stores();
+    }
+    pass.remove_jumps_to_next();
+    let mut program = pass.compact();
+    compact_constants(&mut program);
+    program
+}
+
+/// The jump target of an instruction, if it has one.
+fn target(instruction: &Opcode) -> Option<usize> {
+    match *instruction {
+        Opcode::Jump(target)
+        | Opcode::JumpIfFalse(target)
+        | Opcode::ForRange(_, target)
+        | Opcode::ForIter(_, target)
+        | Opcode::PushHandler(target, _) => Some(target),
+        _ => None,
+    }
+}
+
+fn target_mut(instruction: &mut Opcode) -> Option<&mut usize> {
+    match instruction {
+        Opcode::Jump(target)
+        | Opcode::JumpIfFalse(target)
+        | Opcode::ForRange(_, target)
+        | Opcode::ForIter(_, target)
+        | Opcode::PushHandler(target, _) => Some(target),
+        _ => None,
+    }
+}
+
+/// How many constant operands an instruction could be folded with.
+fn foldable_arity(instruction: &Opcode) -> Option<usize> {
+    match instruction {
+        Opcode::Negate | Opcode::Not | Opcode::Stringify | Opcode::JumpIfFalse(_) => Some(1),
+        Opcode::Add
+        | Opcode::Sub
+        | Opcode::Mul
+        | Opcode::Div
+        | Opcode::Mod
+        | Opcode::Equal
+        | Opcode::NotEqual
+        | Opcode::Greater
+        | Opcode::Less
+        | Opcode::GreaterEqual
+        | Opcode::LessEqual => Some(2),
+        _ => None,
+    }
+}
+
+struct Pass {
+    program: Program,
+    removed: Vec<bool>,
+}
+
+impl Pass {
+    fn new(program: Program) -> Self {
+        let removed = vec![false; program.code.len()];
+        Pass { program, removed }
+    }
+
+    /// The first surviving instruction at or after `ip`; the code length if there is none.
+    fn resolve(&self, mut ip: usize) -> usize {
+        while ip < self.removed.len() && self.removed[ip] {
+            ip += 1;
+        }
+        ip
+    }
+
+    /// The surviving instruction before `ip`, if any.
+    fn previous(&self, ip: usize) -> Option<usize> {
+        (0..ip).rev().find(|&prev| !self.removed[prev])
+    }
+
+    fn entry_points(&self) -> impl Iterator<Item = usize> + '_ {
+        self.program.constants.iter().filter_map(|constant| match constant {
+            Value::Function(function) => Some(function.start_ip),
+            _ => None,
+        })
+    }
+
+    /// Every instruction index something can jump or call to.
+    fn landing_sites(&self) -> Vec<bool> {
+        let mut sites = vec![false; self.program.code.len() + 1];
+        for (ip, instruction) in self.program.code.iter().enumerate() {
+            if let Some(target) = target(instruction).filter(|_| !self.removed[ip]) {
+                sites[target.min(self.program.code.len())] = true;
+            }
+        }
+        for start in self.entry_points() {
+            sites[start.min(self.program.code.len())] = true;
+        }
+        sites
+    }
+
+    /// Where control can go after a surviving instruction.
+    fn successors(&self, ip: usize) -> Vec<usize> {
+        let next = self.resolve(ip + 1);
+        let mut successors = match self.program.code[ip] {
+            Opcode::Return | Opcode::Throw => vec![],
+            Opcode::Jump(_) => vec![],
+            _ => vec![next],
+        };
+        if let Some(target) = target(&self.program.code[ip]) {
+            successors.push(self.resolve(target));
+        }
+        successors.retain(|&succ| succ < self.program.code.len());
+        successors
+    }
+
+    /// Replaces pure operations on constants with their result, and branches on a constant
+    /// condition with a plain jump (or nothing). Works left to right, so `1 + 2 * 3` collapses
+    /// completely: each result is a `LoadConst` the next operation can fold in turn.
+    fn fold_constants(&mut self) {
+        let sites = self.landing_sites();
+        for ip in 0..self.program.code.len() {
+            let Some(arity) = foldable_arity(&self.program.code[ip]) else { continue };
+            if self.removed[ip] {
+                continue;
+            }
+
+            // The operands must be loaded right before, with nothing jumping in between
+            let mut loads = vec![];
+            let mut at = ip;
+            for _ in 0..arity {
+                match self.previous(at) {
+                    Some(prev) if matches!(self.program.code[prev], Opcode::LoadConst(_)) => loads.push(prev),
+                    _ => break,
+                }
+                at = loads[loads.len() - 1];
+            }
+            if loads.len() < arity || sites[at + 1..=ip].iter().any(|&site| site) {
+                continue;
+            }
+            loads.reverse();
+            let operands: Vec<Value> = loads
+                .iter()
+                .map(|&load| match self.program.code[load] {
+                    Opcode::LoadConst(idx) => self.program.constants[idx].clone(),
+                    _ => unreachable!("operands are constant loads"),
+                })
+                .collect();
+
+            if let Opcode::JumpIfFalse(target) = self.program.code[ip] {
+                if operands[0].is_truthy() {
+                    self.removed[loads[0]] = true;
+                } else {
+                    self.program.code[loads[0]] = Opcode::Jump(target);
+                }
+                self.removed[ip] = true;
+                continue;
+            }
+            let Some(result) = vm::fold(&self.program.code[ip], &operands) else { continue };
+            self.program.constants.push(result);
+            self.program.code[loads[0]] = Opcode::LoadConst(self.program.constants.len() - 1);
+            for &load in &loads[1..] {
+                self.removed[load] = true;
+            }
+            self.removed[ip] = true;
+        }
+    }
+
+    /// Points jumps that land on another jump straight at its destination.
+    fn thread_jumps(&mut self) {
+        for ip in 0..self.program.code.len() {
+            if self.removed[ip] {
+                continue;
+            }
+            let Some(mut destination) = target(&self.program.code[ip]) else { continue };
+            // Bounded so a loop of jumps can't hang the compiler
+            for _ in 0..self.program.code.len() {
+                match self.program.code.get(self.resolve(destination)) {
+                    Some(Opcode::Jump(next)) if *next != destination => destination = *next,
+                    _ => break,
+                }
+            }
+            if let Some(target) = target_mut(&mut self.program.code[ip]) {
+                *target = destination;
+            }
+        }
+    }
+
+    /// Drops whatever can't be reached from the script entry or a function that is still
+    /// created somewhere: code after a `return`, `throw` or unconditional jump, and branches
+    /// folded away above. Functions only made by dead code go too.
+    fn remove_unreachable(&mut self) {
+        let starts: HashMap<usize, usize> = self
+            .program
+            .constants
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, constant)| match constant {
+                Value::Function(function) => Some((idx, function.start_ip)),
+                _ => None,
+            })
+            .collect();
+
+        let mut reachable = vec![false; self.program.code.len()];
+        let mut pending = vec![self.resolve(0)];
+        while let Some(ip) = pending.pop() {
+            if ip >= self.program.code.len() || reachable[ip] {
+                continue;
+            }
+            reachable[ip] = true;
+            if let Opcode::MakeFunction(idx) = self.program.code[ip] {
+                if let Some(&start) = starts.get(&idx) {
+                    pending.push(self.resolve(start));
+                }
+            }
+            pending.extend(self.successors(ip));
+        }
+        for (removed, reachable) in self.removed.iter_mut().zip(reachable) {
+            *removed |= !reachable;
+        }
+    }
+
+    /// Drops jumps that would land on the very next surviving instruction anyway.
+    fn remove_jumps_to_next(&mut self) {
+        for ip in 0..self.program.code.len() {
+            if let (false, Opcode::Jump(target)) = (self.removed[ip], &self.program.code[ip]) {
+                if *target > ip && self.resolve(*target) == self.resolve(ip + 1) {
+                    self.removed[ip] = true;
+                }
+            }
+        }
+    }
+
+    /// Drops `StoreLocal x; LoadLocal x` when nothing reads `x` afterwards: the value just stays
+    /// on the stack. Globals are left alone, since any function may read them later. So are
+    /// slots a closure captures or a `catch` block could see.
+    fn remove_dead_stores(&mut self) {
+        let live_out = self.live_locals();
+        let sites = self.landing_sites();
+
+        let mut escaping: BTreeSet<usize> = BTreeSet::new();
+        for constant in &self.program.constants {
+            if let Value::Function(function) = constant {
+                escaping.extend(function.captures.iter().filter(|capture| capture.is_local).map(|capture| capture.index));
+            }
+        }
+        for (ip, instruction) in self.program.code.iter().enumerate() {
+            if let (false, Opcode::PushHandler(catch_ip, _)) = (self.removed[ip], instruction) {
+                let catch_ip = self.resolve(*catch_ip);
+                if let Some(live) = live_out.get(catch_ip) {
+                    escaping.extend(live_in(&self.program.code[catch_ip], live));
+                }
+            }
+        }
+
+        for ip in 0..self.program.code.len() {
+            let Opcode::StoreLocal(slot) = self.program.code[ip] else { continue };
+            let load = self.resolve(ip + 1);
+            if self.removed[ip]
+                || !matches!(self.program.code.get(load), Some(Opcode::LoadLocal(loaded)) if *loaded == slot)
+                || sites[ip + 1..=load].iter().any(|&site| site)
+                || escaping.contains(&slot)
+                || live_out[load].contains(&slot)
+            {
+                continue;
+            }
+            self.removed[ip] = true;
+            self.removed[load] = true;
+        }
+    }
+
+    /// Local slots that may still be read after each instruction, by backwards dataflow. Slot
+    /// numbers are per frame, but no edge crosses from one function's code into another's.
+    fn live_locals(&self) -> Vec<BTreeSet<usize>> {
+        let successors: Vec<Vec<usize>> = (0..self.program.code.len())
+            .map(|ip| if self.removed[ip] { vec![] } else { self.successors(ip) })
+            .collect();
+        let mut live_out = vec![BTreeSet::new(); self.program.code.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for ip in (0..self.program.code.len()).rev() {
+                let mut live = BTreeSet::new();
+                for &succ in &successors[ip] {
+                    live.extend(live_in(&self.program.code[succ], &live_out[succ]));
+                }
+                if live != live_out[ip] {
+                    live_out[ip] = live;
+                    changed = true;
+                }
+            }
+        }
+        live_out
+    }
+
+    /// Renumbers everything around the removed instructions.
+    fn compact(self) -> Program {
+        let Pass { mut program, removed } = self;
+        // New index of every old one; removed instructions map to the next survivor
+        let mut new_index = Vec::with_capacity(removed.len() + 1);
+        let mut kept = 0;
+        for &removed in &removed {
+            new_index.push(kept);
+            kept += usize::from(!removed);
+        }
+        new_index.push(kept);
+
+        let mut code = Vec::with_capacity(kept);
+        let mut lines = Vec::with_capacity(kept);
+        for (ip, mut instruction) in program.code.into_iter().enumerate() {
+            if removed[ip] {
+                continue;
+            }
+            if let Some(target) = target_mut(&mut instruction) {
+                *target = new_index[(*target).min(removed.len())];
+            }
+            code.push(instruction);
+            if let Some(&line) = program.lines.get(ip) {
+                lines.push(line);
+            }
+        }
+        for constant in &mut program.constants {
+            if let Value::Function(function) = constant {
+                *constant = Value::Function(Rc::new(Function {
+                    name: function.name.clone(),
+                    arity: function.arity,
+                    local_count: function.local_count,
+                    captures: function.captures.clone(),
+                    start_ip: new_index[function.start_ip.min(removed.len())],
+                }));
+            }
+        }
+        program.code = code;
+        program.lines = lines;
+        program
+    }
+}
+
+/// Slots live on entry to an instruction, given those live after it.
+fn live_in(instruction: &Opcode, live_out: &BTreeSet<usize>) -> BTreeSet<usize> {
+    let mut live = live_out.clone();
+    match *instruction {
+        Opcode::StoreLocal(slot) => {
+            live.remove(&slot);
+        }
+        Opcode::LoadLocal(slot) => {
+            live.insert(slot);
+        }
+        // The loop state lives in the variable's slot and the one after it
+        Opcode::ForRange(slot, _) | Opcode::ForIter(slot, _) => {
+            live.insert(slot);
+            live.insert(slot + 1);
+        }
+        _ => {}
+    }
+    live
+}
+
+/// Key a constant is deduplicated by. Floats compare by bits so 0.0 and -0.0 stay apart.
+#[derive(PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(u64),
+    Str(Rc<str>),
+}
+
+/// Drops constants nothing loads any more (folded operands, dead functions) and merges
+/// duplicates, keeping the first-used order.
+fn compact_constants(program: &mut Program) {
+    let mut constants = vec![];
+    let mut seen: HashMap<ConstantKey, usize> = HashMap::new();
+    let mut new_index: HashMap<usize, usize> = HashMap::new();
+
+    for instruction in &mut program.code {
+        let (Opcode::LoadConst(idx) | Opcode::MakeFunction(idx)) = instruction else { continue };
+        if let Some(&mapped) = new_index.get(idx) {
+            *idx = mapped;
+            continue;
+        }
+        let constant = &program.constants[*idx];
+        let key = match constant {
+            Value::Nil => Some(ConstantKey::Nil),
+            Value::Bool(b) => Some(ConstantKey::Bool(*b)),
+            Value::Int(n) => Some(ConstantKey::Int(*n)),
+            Value::Float(f) => Some(ConstantKey::Float(f.to_bits())),
+            Value::Str(s) => Some(ConstantKey::Str(s.clone())),
+            _ => None,
+        };
+        let mapped = match key.as_ref().and_then(|key| seen.get(key)) {
+            Some(&existing) => existing,
+            None => {
+                constants.push(constant.clone());
+                if let Some(key) = key {
+                    seen.insert(key, constants.len() - 1);
+                }
+                constants.len() - 1
+            }
+        };
+        new_index.insert(*idx, mapped);
+        *idx = mapped;
+    }
+    program.constants = constants;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::error::{ErrorKind, RuntimeError};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::verifier;
+    use crate::vm::VM;
+
+    const LEVELS: [OptLevel; 3] = [OptLevel::O0, OptLevel::O1, OptLevel::O2];
+
+    fn compile(source: &str, level: OptLevel) -> Program {
+        let ast = Parser::new(Lexer::new(source)).parse().expect("test scripts parse");
+        let mut compiler = Compiler::new();
+        compiler.compile(ast);
+        optimize(compiler.program(), level)
+    }
+
+    fn code(program: &Program) -> String {
+        format!("{:?}", program.code)
+    }
+
+    fn run(source: &str, level: OptLevel) -> Result<Value, RuntimeError> {
+        VM::new(compile(source, level)).run()
+    }
+
+    /// Runs `source` at every level and checks they all give the -O0 result.
+    fn same_at_every_level(source: &str) -> Result<Value, RuntimeError> {
+        let expected = run(source, OptLevel::O0);
+        for level in LEVELS {
+            let program = compile(source, level);
+            assert!(verifier::verify(&program).is_ok(), "{:?} output fails to verify", level);
+            let result = VM::new(program).run();
+            match (&expected, &result) {
+                (Ok(a), Ok(b)) => assert_eq!(a, b, "{:?} changed the result", level),
+                (Err(a), Err(b)) => {
+                    assert_eq!((a.kind, &a.message, a.line), (b.kind, &b.message, b.line), "{:?} changed the error", level)
+                }
+                _ => panic!("{:?} gave {:?}, -O0 gave {:?}", level, result, expected),
+            }
+        }
+        expected
+    }
+
+    #[test]
+    fn levels_agree_on_results() {
+        let scripts = [
+            "return 1 + 2 * 3 - 4 / 2",
+            "return \"a\" + 1 + true + [1, 2]",
+            "let x = 10
+if 2 > 1 { x = x * 2 } else { x = 0 }
+while false { x = -1 }
+return x",
+            "let total = 0
+for i in 0..10 {
+    if i % 2 == 0 { continue }
+    if i > 7 { break }
+    total += i
+}
+return total",
+            "fn counter() {
+    let n = 0
+    fn next() {
+        n += 1
+        return n
+    }
+    return next
+}
+let next = counter()
+next()
+next()
+return next()",
+            "fn f(a) {
+    let unused = a * 2
+    let b = a + 1
+    return b
+}
+return [f(1), f(2), 0 or \"x\", 1 and 0, false and missing()]",
+            "let seen = []
+for key in { \"b\": 2, \"a\": 1 } { seen = seen + [key] }
+for c in \"hi\" { seen = seen + [c] }
+return seen",
+        ];
+        for source in scripts {
+            assert!(same_at_every_level(source).is_ok(), "{} failed", source);
+        }
+    }
+
+    #[test]
+    fn levels_agree_on_errors() {
+        let overflow = same_at_every_level("let a = 1\nreturn 9223372036854775807 + 1").unwrap_err();
+        assert_eq!(overflow.kind, ErrorKind::Overflow);
+        let division = same_at_every_level("let a = 1\nreturn 1 / 0").unwrap_err();
+        assert_eq!(division.kind, ErrorKind::DivisionByZero);
+        let caught = same_at_every_level("try {\n    throw \"bad\"\n} catch e {\n    return e\n}").unwrap();
+        assert_eq!(caught.to_string(), "bad");
+    }
+
+    #[test]
+    fn o0_leaves_the_program_alone() {
+        let program = compile("if false { print 1 + 2 }\nreturn 3", OptLevel::O0);
+        assert_eq!(code(&optimize(program.clone(), OptLevel::O0)), code(&program));
+        assert!(program.code.iter().any(|op| matches!(op, Opcode::Add)));
+    }
+
+    #[test]
+    fn o1_folds_constants_and_drops_dead_branches() {
+        let program = compile("return 1 + 2 * 3", OptLevel::O1);
+        assert!(matches!(program.code[..], [Opcode::LoadConst(_), Opcode::Return, ..]));
+        assert_eq!(VM::new(program).run().unwrap(), Value::Int(7));
+
+        let program = compile("if false { print \"never\" }\nreturn 1", OptLevel::O1);
+        assert!(!program.code.iter().any(|op| matches!(op, Opcode::Print)));
+    }
+
+    #[test]
+    fn o2_does_at_least_as_much_as_o1() {
+        let source = "fn f(a) {
+    let b = a
+    let c = b
+    return c
+}
+let x = 0
+while x < 3 { x += 1 }
+return f(x)";
+        let o1 = compile(source, OptLevel::O1).code.len();
+        let o2 = compile(source, OptLevel::O2).code.len();
+        assert!(o2 <= o1, "-O2 emitted {} instructions, -O1 {}", o2, o1);
+    }
+}
@@ -1,276 +1,920 @@
 // src/parser.rs - FalconCore Parser (Enhanced with repeat, fn, return, network.scan)
+use crate::diagnostic::Diagnostic;
 use crate::lexer::{Lexer, Token, TokenType};
+use crate::span::{Span, Spanned};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Number(i64),
+    Float(f64),
+    Bool(bool),
     String(String),
+    /// `"a ${x} b"`: literal pieces and embedded expressions, joined as strings
+    Interpolation(Vec<Spanned<Expr>>),
     Identifier(String),
     Binary {
-        left: Box<Expr>,
+        left: Box<Spanned<Expr>>,
         op: TokenType,
-        right: Box<Expr>,
+        right: Box<Spanned<Expr>>,
+    },
+    Unary {
+        op: TokenType,
+        operand: Box<Spanned<Expr>>,
+    },
+    Call {
+        callee: Box<Spanned<Expr>>,
+        args: Vec<Spanned<Expr>>,
+    },
+    Member {
+        object: Box<Spanned<Expr>>,
+        name: String,
+    },
+    List(Vec<Spanned<Expr>>),
+    Map(Vec<(Spanned<Expr>, Spanned<Expr>)>),
+    Index {
+        object: Box<Spanned<Expr>>,
+        index: Box<Spanned<Expr>>,
+    },
+    /// `object[index] = value`, or a compound form when `op` is set.
+    SetIndex {
+        object: Box<Spanned<Expr>>,
+        index: Box<Spanned<Expr>>,
+        op: Option<TokenType>,
+        value: Box<Spanned<Expr>>,
     },
     Let {
         is_secure: bool,
         is_const: bool,
+        // Spanned on its own so redeclaration errors can point at the name
+        name: Spanned<String>,
+        value: Box<Spanned<Expr>>,
+        /// `///` comment above a `secure const`
+        doc: Option<String>,
+    },
+    Assign {
         name: String,
-        value: Box<Expr>,
+        // The binary operator of a compound assignment (`+=` carries Plus), None for plain `=`
+        op: Option<TokenType>,
+        value: Box<Spanned<Expr>>,
     },
     Print {
-        expr: Box<Expr>,
+        expr: Box<Spanned<Expr>>,
     },
+    /// `if` followed by any `elseif`s: each branch is a condition and its body, tried in order.
     If {
-        condition: Box<Expr>,
-        then_branch: Vec<Expr>,
-        else_branch: Option<Vec<Expr>>,
+        branches: Vec<(Spanned<Expr>, Vec<Spanned<Expr>>)>,
+        else_branch: Option<Vec<Spanned<Expr>>>,
     },
     Repeat {
-        times: Box<Expr>,
-        body: Vec<Expr>,
+        times: Box<Spanned<Expr>>,
+        body: Vec<Spanned<Expr>>,
+    },
+    While {
+        condition: Box<Spanned<Expr>>,
+        body: Vec<Spanned<Expr>>,
+    },
+    ForIn {
+        var: String,
+        iterable: Box<Spanned<Expr>>,
+        body: Vec<Spanned<Expr>>,
+    },
+    /// `for var in start..end`, end exclusive
+    ForRange {
+        var: String,
+        start: Box<Spanned<Expr>>,
+        end: Box<Spanned<Expr>>,
+        body: Vec<Spanned<Expr>>,
+    },
+    Break,
+    Continue,
+    Throw {
+        value: Box<Spanned<Expr>>,
+    },
+    /// `try { } catch e { } finally { }`; at least one of `catch` and `finally` is present
+    Try {
+        body: Vec<Spanned<Expr>>,
+        // The variable bound to the error, and the handler body
+        catch: Option<(String, Vec<Spanned<Expr>>)>,
+        finally: Option<Vec<Spanned<Expr>>>,
     },
     FnDef {
         name: String,
         params: Vec<String>,
-        body: Vec<Expr>,
+        body: Vec<Spanned<Expr>>,
+        /// `///` comment above the `fn`
+        doc: Option<String>,
     },
     Return {
-        value: Option<Box<Expr>>,
-    },
-    NetworkScan {
-        subnet: Box<Expr>,
+        value: Option<Box<Spanned<Expr>>>,
     },
 }
 
+// Boxed so the happy path through every parse method stays small
+type ParseResult<T> = Result<T, Box<Diagnostic>>;
+
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     current_token: Token,
+    // Span of the last token consumed, where the node being parsed ends
+    previous: Span,
+    file: String,
+    diagnostics: Vec<Diagnostic>,
+    consumed: usize,
+    // `///` lines directly before the current token
+    pending_doc: Vec<String>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(lexer: Lexer<'a>) -> Self {
         let mut parser = Parser {
             lexer,
-            current_token: Token { kind: TokenType::Eof, line: 1, column: 1 },
+            current_token: Token {
+                kind: TokenType::Eof,
+                span: Span::default(),
+            },
+            previous: Span::default(),
+            file: "<input>".to_string(),
+            diagnostics: vec![],
+            consumed: 0,
+            pending_doc: vec![],
         };
-        parser.current_token = parser.lexer.next_token();
+        parser.current_token = parser.next_token();
         parser
     }
 
-    fn eat(&mut self, expected: TokenType) {
+    /// Sets the file name reported in diagnostics.
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file = file.to_string();
+        self
+    }
+
+    fn error(&self, message: String) -> Box<Diagnostic> {
+        Box::new(Diagnostic::new(
+            &self.file,
+            self.lexer.source(),
+            self.current_token.span.line,
+            self.current_token.span.column,
+            message,
+        ))
+    }
+
+    fn expected(&self, expected: &str) -> Box<Diagnostic> {
+        let found = format!("{:?}", self.current_token.kind);
+        let diagnostic = self.error(format!("Expected {}, found {}", expected, found));
+        Box::new(diagnostic.with_expected(expected.to_string(), found))
+    }
+
+    fn eat(&mut self, expected: TokenType) -> ParseResult<()> {
         if self.current_token.kind == expected {
-            self.current_token = self.lexer.next_token();
+            self.advance();
+            Ok(())
+        } else {
+            Err(self.expected(&format!("{:?}", expected)))
+        }
+    }
+
+    fn identifier(&mut self, what: &str) -> ParseResult<String> {
+        if let TokenType::Identifier(name) = self.current_token.kind.clone() {
+            self.advance();
+            Ok(name)
         } else {
-            panic!("Expected {:?}, found {:?}", expected, self.current_token.kind);
+            Err(self.expected(what))
         }
     }
 
-    pub fn parse(&mut self) -> Vec<Expr> {
+    /// `node` spanning from `start` to the end of the last token consumed.
+    fn spanned(&self, node: Expr, start: Span) -> Spanned<Expr> {
+        Spanned::new(node, start.to(self.previous))
+    }
+
+    pub fn parse(&mut self) -> Result<Vec<Spanned<Expr>>, Vec<Diagnostic>> {
         let mut statements = vec![];
 
         while self.current_token.kind != TokenType::Eof {
-            statements.push(self.statement());
+            if let Some(stmt) = self.statement_or_recover() {
+                statements.push(stmt);
+            }
+        }
+
+        if self.diagnostics.is_empty() {
+            Ok(statements)
+        } else {
+            Err(std::mem::take(&mut self.diagnostics))
+        }
+    }
+
+    /// Parses one statement; on a syntax error records it and skips to the next statement boundary.
+    fn statement_or_recover(&mut self) -> Option<Spanned<Expr>> {
+        let start = self.consumed;
+        match self.statement() {
+            Ok(stmt) => Some(stmt),
+            Err(diagnostic) => {
+                self.diagnostics.push(*diagnostic);
+                // Always make progress so a bad token can't stall the parser
+                if self.consumed == start {
+                    self.advance();
+                }
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    fn synchronize(&mut self) {
+        // Skip whole blocks opened after the error so their closing brace isn't reported again
+        let mut depth = 0;
+        loop {
+            match self.current_token.kind {
+                TokenType::Eof => return,
+                TokenType::LBrace => {
+                    depth += 1;
+                    self.advance();
+                }
+                TokenType::RBrace if depth > 0 => {
+                    depth -= 1;
+                    self.advance();
+                }
+                _ if depth > 0 => self.advance(),
+                TokenType::RBrace
+                | TokenType::Let
+                | TokenType::Const
+                | TokenType::SecureLet
+                | TokenType::SecureConst
+                | TokenType::Print
+                | TokenType::If
+                | TokenType::ElseIf
+                | TokenType::Else
+                | TokenType::EndIf
+                | TokenType::EndRepeat
+                | TokenType::Repeat
+                | TokenType::While
+                | TokenType::For
+                | TokenType::Break
+                | TokenType::Continue
+                | TokenType::Try
+                | TokenType::Throw
+                | TokenType::Fn
+                | TokenType::Return
+                | TokenType::NetworkScan => return,
+                _ => self.advance(),
+            }
         }
+    }
 
-        statements
+    fn block(&mut self) -> ParseResult<Vec<Spanned<Expr>>> {
+        self.eat(TokenType::LBrace)?;
+        let mut body = vec![];
+        while self.current_token.kind != TokenType::RBrace {
+            if self.current_token.kind == TokenType::Eof {
+                return Err(self.expected("RBrace"));
+            }
+            if let Some(stmt) = self.statement_or_recover() {
+                body.push(stmt);
+            }
+        }
+        self.eat(TokenType::RBrace)?;
+        Ok(body)
     }
 
-    fn statement(&mut self) -> Expr {
-        match self.current_token.kind {
-            TokenType::SecureLet => self.let_statement(true, false),
-            TokenType::SecureConst => self.let_statement(true, true),
+    /// Statements up to (not including) one of `terminators`, for keyword-terminated blocks.
+    fn block_until(&mut self, terminators: &[TokenType], closing: &str) -> ParseResult<Vec<Spanned<Expr>>> {
+        let mut body = vec![];
+        while !terminators.contains(&self.current_token.kind) {
+            if self.current_token.kind == TokenType::Eof {
+                return Err(self.expected(closing));
+            }
+            if let Some(stmt) = self.statement_or_recover() {
+                body.push(stmt);
+            }
+        }
+        Ok(body)
+    }
+
+    fn statement(&mut self) -> ParseResult<Spanned<Expr>> {
+        // Doc comments only document the `fn` or `secure const` right after them
+        let doc = std::mem::take(&mut self.pending_doc);
+        let doc = if doc.is_empty() { None } else { Some(doc.join("\n")) };
+
+        let start = self.current_token.span;
+        let stmt = match self.current_token.kind {
+            TokenType::Let => self.let_statement(false, false, None),
+            TokenType::Const => self.let_statement(false, true, None),
+            TokenType::SecureLet => self.let_statement(true, false, None),
+            TokenType::SecureConst => self.let_statement(true, true, doc),
             TokenType::Print => self.print_statement(),
             TokenType::If => self.if_statement(),
             TokenType::Repeat => self.repeat_statement(),
-            TokenType::Fn => self.fn_statement(),
+            TokenType::While => self.while_statement(),
+            TokenType::For => self.for_statement(),
+            TokenType::Break | TokenType::Continue => self.loop_jump_statement(),
+            TokenType::Throw => self.throw_statement(),
+            TokenType::Try => self.try_statement(),
+            TokenType::Fn => self.fn_statement(doc),
             TokenType::Return => self.return_statement(),
-            TokenType::NetworkScan => self.network_scan_statement(),
-            _ => self.expr(),
-        }
+            TokenType::RBrace => Err(self.error("Unexpected RBrace".to_string())),
+            _ => return self.expression_statement(),
+        }?;
+        Ok(self.spanned(stmt, start))
     }
 
-    fn let_statement(&mut self, is_secure: bool, is_const: bool) -> Expr {
-        if is_const {
-            self.eat(TokenType::SecureConst);
-        } else {
-            self.eat(TokenType::SecureLet);
+    /// An expression, or an assignment when the expression turns out to be a plain variable.
+    fn expression_statement(&mut self) -> ParseResult<Spanned<Expr>> {
+        let target = self.expr()?;
+
+        let op = match self.current_token.kind {
+            TokenType::Assign => None,
+            TokenType::PlusAssign => Some(TokenType::Plus),
+            TokenType::MinusAssign => Some(TokenType::Minus),
+            TokenType::StarAssign => Some(TokenType::Star),
+            TokenType::SlashAssign => Some(TokenType::Slash),
+            _ => return Ok(target),
+        };
+
+        match target.node {
+            Expr::Identifier(name) => {
+                self.advance();
+                let value = self.expr()?;
+                let assign = Expr::Assign {
+                    name,
+                    op,
+                    value: Box::new(value),
+                };
+                Ok(self.spanned(assign, target.span))
+            }
+            Expr::Index { object, index } => {
+                self.advance();
+                let value = self.expr()?;
+                let set_index = Expr::SetIndex {
+                    object,
+                    index,
+                    op,
+                    value: Box::new(value),
+                };
+                Ok(self.spanned(set_index, target.span))
+            }
+            _ => Err(Box::new(Diagnostic::new(
+                &self.file,
+                self.lexer.source(),
+                target.span.line,
+                target.span.column,
+                "Invalid assignment target".to_string(),
+            ))),
         }
+    }
 
-        let name = if let TokenType::Identifier(n) = self.current_token.kind.clone() {
-            self.eat(TokenType::Identifier(n.clone()));
-            n
-        } else {
-            panic!("Expected identifier after secure let/const");
-        };
+    fn let_statement(&mut self, is_secure: bool, is_const: bool, doc: Option<String>) -> ParseResult<Expr> {
+        // let / const / secure let / secure const
+        self.advance();
+
+        let start = self.current_token.span;
+        let name = self.identifier("identifier after let/const")?;
+        let name = Spanned::new(name, start.to(self.previous));
 
-        self.eat(TokenType::Assign);
-        let value = self.expr();
+        self.eat(TokenType::Assign)?;
+        let value = self.expr()?;
 
-        Expr::Let {
+        Ok(Expr::Let {
             is_secure,
             is_const,
             name,
             value: Box::new(value),
-        }
+            doc,
+        })
     }
 
-    fn print_statement(&mut self) -> Expr {
-        self.eat(TokenType::Print);
-        let expr = self.expr();
-        Expr::Print {
+    fn print_statement(&mut self) -> ParseResult<Expr> {
+        self.eat(TokenType::Print)?;
+        let expr = self.expr()?;
+        Ok(Expr::Print {
             expr: Box::new(expr),
-        }
+        })
     }
 
-    fn if_statement(&mut self) -> Expr {
-        self.eat(TokenType::If);
-        let condition = self.expr();
+    /// `if c { } elseif c { } else { }`, or the keyword form `if c ... elseif c ... else ... endif`.
+    fn if_statement(&mut self) -> ParseResult<Expr> {
+        self.eat(TokenType::If)?;
+        let condition = self.expr()?;
+        let braced = self.current_token.kind == TokenType::LBrace;
+        let terminators = [TokenType::ElseIf, TokenType::Else, TokenType::EndIf];
 
-        self.eat(TokenType::LBrace);
-        let mut then_branch = vec![];
-        while self.current_token.kind != TokenType::RBrace {
-            then_branch.push(self.statement());
+        let body = if braced { self.block()? } else { self.block_until(&terminators, "endif")? };
+        let mut branches = vec![(condition, body)];
+
+        while self.current_token.kind == TokenType::ElseIf {
+            self.advance();
+            let condition = self.expr()?;
+            let body = if braced { self.block()? } else { self.block_until(&terminators, "endif")? };
+            branches.push((condition, body));
         }
-        self.eat(TokenType::RBrace);
 
         let else_branch = if self.current_token.kind == TokenType::Else {
-            self.eat(TokenType::Else);
-            self.eat(TokenType::LBrace);
-            let mut else_stmts = vec![];
-            while self.current_token.kind != TokenType::RBrace {
-                else_stmts.push(self.statement());
-            }
-            self.eat(TokenType::RBrace);
-            Some(else_stmts)
+            self.advance();
+            Some(if braced { self.block()? } else { self.block_until(&[TokenType::EndIf], "endif")? })
         } else {
             None
         };
-
-        Expr::If {
-            condition: Box::new(condition),
-            then_branch,
-            else_branch,
+        if !braced {
+            self.eat(TokenType::EndIf)?;
         }
+
+        Ok(Expr::If { branches, else_branch })
     }
 
-    fn repeat_statement(&mut self) -> Expr {
-        self.eat(TokenType::Repeat);
-        let times = self.expr();
+    /// `repeat n { }`, or the keyword form `repeat n ... endrepeat`.
+    fn repeat_statement(&mut self) -> ParseResult<Expr> {
+        self.eat(TokenType::Repeat)?;
+        let times = self.expr()?;
 
-        self.eat(TokenType::LBrace);
-        let mut body = vec![];
-        while self.current_token.kind != TokenType::RBrace {
-            body.push(self.statement());
-        }
-        self.eat(TokenType::RBrace);
+        let body = if self.current_token.kind == TokenType::LBrace {
+            self.block()?
+        } else {
+            let body = self.block_until(&[TokenType::EndRepeat], "endrepeat")?;
+            self.eat(TokenType::EndRepeat)?;
+            body
+        };
 
-        Expr::Repeat {
+        Ok(Expr::Repeat {
             times: Box::new(times),
             body,
+        })
+    }
+
+    fn while_statement(&mut self) -> ParseResult<Expr> {
+        self.eat(TokenType::While)?;
+        let condition = self.expr()?;
+
+        let body = self.block()?;
+
+        Ok(Expr::While {
+            condition: Box::new(condition),
+            body,
+        })
+    }
+
+    /// `for x in iterable { }` or `for i in start..end { }`.
+    fn for_statement(&mut self) -> ParseResult<Expr> {
+        self.eat(TokenType::For)?;
+        let var = self.identifier("loop variable after for")?;
+        self.eat(TokenType::In)?;
+        let iterable = self.expr()?;
+
+        if self.current_token.kind == TokenType::DotDot {
+            self.advance();
+            let end = self.expr()?;
+            let body = self.block()?;
+            return Ok(Expr::ForRange {
+                var,
+                start: Box::new(iterable),
+                end: Box::new(end),
+                body,
+            });
         }
+
+        let body = self.block()?;
+        Ok(Expr::ForIn {
+            var,
+            iterable: Box::new(iterable),
+            body,
+        })
+    }
+
+    fn loop_jump_statement(&mut self) -> ParseResult<Expr> {
+        let is_break = self.current_token.kind == TokenType::Break;
+        self.advance();
+        Ok(if is_break { Expr::Break } else { Expr::Continue })
+    }
+
+    fn throw_statement(&mut self) -> ParseResult<Expr> {
+        self.eat(TokenType::Throw)?;
+        let value = self.expr()?;
+        Ok(Expr::Throw {
+            value: Box::new(value),
+        })
     }
 
-    fn fn_statement(&mut self) -> Expr {
-        self.eat(TokenType::Fn);
-        let name = if let TokenType::Identifier(n) = self.current_token.kind.clone() {
-            self.eat(TokenType::Identifier(n.clone()));
-            n
+    fn try_statement(&mut self) -> ParseResult<Expr> {
+        self.eat(TokenType::Try)?;
+        let body = self.block()?;
+
+        let catch = if self.current_token.kind == TokenType::Catch {
+            self.advance();
+            let var = self.identifier("error variable after catch")?;
+            Some((var, self.block()?))
+        } else {
+            None
+        };
+        let finally = if self.current_token.kind == TokenType::Finally {
+            self.advance();
+            Some(self.block()?)
         } else {
-            panic!("Expected function name");
+            None
         };
+        if catch.is_none() && finally.is_none() {
+            return Err(self.expected("Catch or Finally"));
+        }
+
+        Ok(Expr::Try { body, catch, finally })
+    }
 
-        self.eat(TokenType::LParen);
+    fn fn_statement(&mut self, doc: Option<String>) -> ParseResult<Expr> {
+        self.eat(TokenType::Fn)?;
+        let name = self.identifier("function name")?;
+
+        self.eat(TokenType::LParen)?;
         let mut params = vec![];
         while self.current_token.kind != TokenType::RParen {
-            if let TokenType::Identifier(p) = self.current_token.kind.clone() {
-                params.push(p);
-                self.eat(TokenType::Identifier(p));
-            }
+            params.push(self.identifier("parameter name")?);
             if self.current_token.kind == TokenType::Comma {
-                self.eat(TokenType::Comma);
+                self.eat(TokenType::Comma)?;
+            } else if self.current_token.kind != TokenType::RParen {
+                return Err(self.expected("Comma or RParen"));
             }
         }
-        self.eat(TokenType::RParen);
+        self.eat(TokenType::RParen)?;
 
-        self.eat(TokenType::LBrace);
-        let mut body = vec![];
-        while self.current_token.kind != TokenType::RBrace {
-            body.push(self.statement());
-        }
-        self.eat(TokenType::RBrace);
+        let body = self.block()?;
 
-        Expr::FnDef {
+        Ok(Expr::FnDef {
             name,
             params,
             body,
-        }
+            doc,
+        })
     }
 
-    fn return_statement(&mut self) -> Expr {
-        self.eat(TokenType::Return);
+    fn return_statement(&mut self) -> ParseResult<Expr> {
+        self.eat(TokenType::Return)?;
         let value = if self.current_token.kind != TokenType::Semi && self.current_token.kind != TokenType::RBrace {
-            Some(Box::new(self.expr()))
+            Some(Box::new(self.expr()?))
         } else {
             None
         };
-        Expr::Return { value }
+        Ok(Expr::Return { value })
     }
 
-    fn network_scan_statement(&mut self) -> Expr {
-        self.eat(TokenType::NetworkScan);
-        let subnet = self.expr();
-        Expr::NetworkScan {
-            subnet: Box::new(subnet),
-        }
-    }
-
-    fn expr(&mut self) -> Expr {
-        self.term()
+    fn expr(&mut self) -> ParseResult<Spanned<Expr>> {
+        self.binary(1)
     }
 
-    fn term(&mut self) -> Expr {
-        let mut left = self.factor();
+    /// Precedence climbing over the binary operators; every level is left-associative.
+    fn binary(&mut self, min_precedence: u8) -> ParseResult<Spanned<Expr>> {
+        let mut left = self.unary()?;
 
-        while matches!(self.current_token.kind, TokenType::Plus | TokenType::Minus) {
+        while let Some(precedence) = binary_precedence(&self.current_token.kind) {
+            if precedence < min_precedence {
+                break;
+            }
             let op = self.current_token.kind.clone();
             self.advance();
-            let right = self.factor();
-            left = Expr::Binary {
+            let right = self.binary(precedence + 1)?;
+            let start = left.span;
+            let binary = Expr::Binary {
                 left: Box::new(left),
                 op,
                 right: Box::new(right),
             };
+            left = self.spanned(binary, start);
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> ParseResult<Spanned<Expr>> {
+        if matches!(self.current_token.kind, TokenType::Minus | TokenType::Bang) {
+            let start = self.current_token.span;
+            let op = self.current_token.kind.clone();
+            self.advance();
+            let operand = self.unary()?;
+            let unary = Expr::Unary {
+                op,
+                operand: Box::new(operand),
+            };
+            return Ok(self.spanned(unary, start));
         }
+        self.postfix()
+    }
 
-        left
+    fn postfix(&mut self) -> ParseResult<Spanned<Expr>> {
+        let mut expr = self.primary()?;
+
+        loop {
+            let start = expr.span;
+            let node = match self.current_token.kind {
+                TokenType::LParen => {
+                    self.advance();
+                    let mut args = vec![];
+                    while self.current_token.kind != TokenType::RParen {
+                        args.push(self.expr()?);
+                        if self.current_token.kind == TokenType::Comma {
+                            self.advance();
+                        } else if self.current_token.kind != TokenType::RParen {
+                            return Err(self.expected("Comma or RParen"));
+                        }
+                    }
+                    self.eat(TokenType::RParen)?;
+                    Expr::Call {
+                        callee: Box::new(expr),
+                        args,
+                    }
+                }
+                TokenType::LBracket => {
+                    self.advance();
+                    let index = self.expr()?;
+                    self.eat(TokenType::RBracket)?;
+                    Expr::Index {
+                        object: Box::new(expr),
+                        index: Box::new(index),
+                    }
+                }
+                TokenType::Dot => {
+                    self.advance();
+                    let name = self.identifier("member name after '.'")?;
+                    Expr::Member {
+                        object: Box::new(expr),
+                        name,
+                    }
+                }
+                _ => return Ok(expr),
+            };
+            expr = self.spanned(node, start);
+        }
     }
 
-    fn factor(&mut self) -> Expr {
-        match self.current_token.kind {
+    fn primary(&mut self) -> ParseResult<Spanned<Expr>> {
+        let start = self.current_token.span;
+        let node = match self.current_token.kind.clone() {
             TokenType::Number(n) => {
                 self.advance();
                 Expr::Number(n)
             }
+            TokenType::Float(f) => {
+                self.advance();
+                Expr::Float(f)
+            }
+            TokenType::True => {
+                self.advance();
+                Expr::Bool(true)
+            }
+            TokenType::False => {
+                self.advance();
+                Expr::Bool(false)
+            }
             TokenType::String(s) => {
                 self.advance();
                 Expr::String(s)
             }
-            TokenType::Identifier(id) => {
+            TokenType::StringPart(s) => self.interpolation(s)?,
+            TokenType::Error(message) => return Err(self.error(message)),
+            TokenType::Identifier(name) => {
+                self.advance();
+                Expr::Identifier(name)
+            }
+            TokenType::LParen => {
+                self.advance();
+                let mut expr = self.expr()?;
+                self.eat(TokenType::RParen)?;
+                // A group's span takes in its parentheses
+                expr.span = start.to(self.previous);
+                return Ok(expr);
+            }
+            TokenType::LBracket => {
+                self.advance();
+                let mut items = vec![];
+                while self.current_token.kind != TokenType::RBracket {
+                    items.push(self.expr()?);
+                    if self.current_token.kind == TokenType::Comma {
+                        self.advance();
+                    } else if self.current_token.kind != TokenType::RBracket {
+                        return Err(self.expected("Comma or RBracket"));
+                    }
+                }
+                self.eat(TokenType::RBracket)?;
+                Expr::List(items)
+            }
+            // In expression position a brace opens a map literal, never a block
+            TokenType::LBrace => {
                 self.advance();
-                Expr::Identifier(id)
+                let mut entries = vec![];
+                while self.current_token.kind != TokenType::RBrace {
+                    let key = self.expr()?;
+                    self.eat(TokenType::Colon)?;
+                    entries.push((key, self.expr()?));
+                    if self.current_token.kind == TokenType::Comma {
+                        self.advance();
+                    } else if self.current_token.kind != TokenType::RBrace {
+                        return Err(self.expected("Comma or RBrace"));
+                    }
+                }
+                self.eat(TokenType::RBrace)?;
+                Expr::Map(entries)
             }
-            _ => panic!("Unexpected token in factor: {:?}", self.current_token.kind),
+            TokenType::NetworkScan => return self.builtin("network.scan", 1),
+            TokenType::CryptoRandom => return self.builtin("crypto.random", 2),
+            TokenType::TimeNow => return self.builtin("time.now", 0),
+            TokenType::TimeMonotonic => return self.builtin("time.monotonic", 0),
+            TokenType::Wait => return self.builtin("wait", 1),
+            _ => return Err(self.expected("expression")),
+        };
+        Ok(self.spanned(node, start))
+    }
+
+    /// The rest of an interpolated string whose first literal piece was `head`.
+    fn interpolation(&mut self, head: String) -> ParseResult<Expr> {
+        let mut parts = vec![];
+        if !head.is_empty() {
+            parts.push(Spanned::new(Expr::String(head), self.current_token.span));
         }
+        loop {
+            self.advance();
+            parts.push(self.expr()?);
+            match self.current_token.kind.clone() {
+                TokenType::StringPart(piece) => {
+                    if !piece.is_empty() {
+                        parts.push(Spanned::new(Expr::String(piece), self.current_token.span));
+                    }
+                }
+                TokenType::String(tail) => {
+                    if !tail.is_empty() {
+                        parts.push(Spanned::new(Expr::String(tail), self.current_token.span));
+                    }
+                    self.advance();
+                    return Ok(Expr::Interpolation(parts));
+                }
+                TokenType::Error(message) => return Err(self.error(message)),
+                _ => return Err(self.expected("'}' closing the interpolation")),
+            }
+        }
+    }
+
+    /// A builtin command: either called normally, `wait(100)`, or command-style with exactly
+    /// `arity` space-separated operands, `crypto.random 33 126`.
+    fn builtin(&mut self, name: &str, arity: usize) -> ParseResult<Spanned<Expr>> {
+        let start = self.current_token.span;
+        self.advance();
+        let callee = self.spanned(Expr::Identifier(name.to_string()), start);
+        if self.current_token.kind == TokenType::LParen {
+            // postfix() parses the argument list
+            return Ok(callee);
+        }
+
+        let mut args = vec![];
+        for _ in 0..arity {
+            args.push(self.unary()?);
+        }
+        let call = Expr::Call {
+            callee: Box::new(callee),
+            args,
+        };
+        Ok(self.spanned(call, start))
     }
 
     fn advance(&mut self) {
-        self.current_token = self.lexer.next_token();
-    }
-                 }
-fn network_scan_statement(&mut self) -> Expr {
-    self.eat(TokenType::NetworkScan);
-    let subnet = self.expr();
-    Expr::NetworkScan {
-        subnet: Box::new(subnet),
+        self.previous = self.current_token.span;
+        self.current_token = self.next_token();
+        self.consumed += 1;
+    }
+
+    /// The next token that isn't a doc comment; doc comment text is kept for the next statement.
+    fn next_token(&mut self) -> Token {
+        self.pending_doc.clear();
+        loop {
+            let token = self.lexer.next_token();
+            match token.kind {
+                TokenType::DocComment(text) => self.pending_doc.push(text),
+                _ => return token,
+            }
+        }
+    }
+}
+
+/// Binding power of each binary operator, loosest first.
+fn binary_precedence(kind: &TokenType) -> Option<u8> {
+    match kind {
+        TokenType::Or => Some(1),
+        TokenType::And => Some(2),
+        TokenType::EqualEqual | TokenType::NotEqual => Some(3),
+        TokenType::Greater | TokenType::Less | TokenType::GreaterEqual | TokenType::LessEqual => Some(4),
+        TokenType::Plus | TokenType::Minus => Some(5),
+        TokenType::Star | TokenType::Slash | TokenType::Percent => Some(6),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Vec<Spanned<Expr>> {
+        Parser::new(Lexer::new(source)).parse().expect("test scripts parse")
+    }
+
+    /// The expression fully parenthesized, operators first.
+    fn shape(expr: &Spanned<Expr>) -> String {
+        let op = |op: &TokenType| match op {
+            TokenType::Plus => "+".to_string(),
+            TokenType::Minus => "-".to_string(),
+            TokenType::Star => "*".to_string(),
+            TokenType::Slash => "/".to_string(),
+            TokenType::Percent => "%".to_string(),
+            TokenType::Less => "<".to_string(),
+            TokenType::EqualEqual => "==".to_string(),
+            TokenType::Bang => "!".to_string(),
+            other => format!("{:?}", other).to_lowercase(),
+        };
+        match &expr.node {
+            Expr::Number(n) => n.to_string(),
+            Expr::Identifier(name) => name.clone(),
+            Expr::Binary { left, op: kind, right } => format!("({} {} {})", op(kind), shape(left), shape(right)),
+            Expr::Unary { op: kind, operand } => format!("({} {})", op(kind), shape(operand)),
+            other => format!("{:?}", other),
+        }
+    }
+
+    fn parse_expr(source: &str) -> String {
+        shape(&parse(source)[0])
+    }
+
+    #[test]
+    fn binary_operators_follow_precedence() {
+        assert_eq!(parse_expr("1 + 2 * 3"), "(+ 1 (* 2 3))");
+        assert_eq!(parse_expr("(1 + 2) * 3"), "(* (+ 1 2) 3)");
+        assert_eq!(parse_expr("a < b + 1 == c"), "(== (< a (+ b 1)) c)");
+        assert_eq!(parse_expr("a or b and c == d"), "(or a (and b (== c d)))");
+        assert_eq!(parse_expr("-a * !b"), "(* (- a) (! b))");
+    }
+
+    #[test]
+    fn binary_operators_are_left_associative() {
+        assert_eq!(parse_expr("10 - 4 - 3"), "(- (- 10 4) 3)");
+        assert_eq!(parse_expr("8 / 4 / 2"), "(/ (/ 8 4) 2)");
+        assert_eq!(parse_expr("7 % 4 * 2"), "(* (% 7 4) 2)");
+        assert_eq!(parse_expr("a or b or c"), "(or (or a b) c)");
+    }
+
+    /// Conditions of each `if` branch, and whether there's an else.
+    fn branches(stmt: &Spanned<Expr>) -> (Vec<String>, bool) {
+        let Expr::If { branches, else_branch } = &stmt.node else { panic!("not an if: {:?}", stmt.node) };
+        (branches.iter().map(|(condition, _)| shape(condition)).collect(), else_branch.is_some())
+    }
+
+    #[test]
+    fn elseif_chains_braced_and_keyword_forms() {
+        let braced = "if a { print 1 } elseif b { print 2 } elseif c { print 3 } else { print 4 }";
+        assert_eq!(branches(&parse(braced)[0]), (vec!["a".to_string(), "b".to_string(), "c".to_string()], true));
+
+        let keywords = "if a\n    print 1\nelseif b\n    print 2\nendif\nrepeat 2\n    print 3\nendrepeat";
+        let ast = parse(keywords);
+        assert_eq!(branches(&ast[0]), (vec!["a".to_string(), "b".to_string()], false));
+        assert!(matches!(&ast[1].node, Expr::Repeat { body, .. } if body.len() == 1));
+    }
+
+    #[test]
+    fn keyword_blocks_need_their_terminator() {
+        let diagnostics = Parser::new(Lexer::new("if a\n    print 1\n")).parse().expect_err("rejected");
+        assert!(diagnostics[0].to_string().contains("endif"), "{}", diagnostics[0]);
+    }
+
+    fn doc(stmt: &Spanned<Expr>) -> Option<&str> {
+        match &stmt.node {
+            Expr::FnDef { doc, .. } | Expr::Let { doc, .. } => doc.as_deref(),
+            other => panic!("no doc on {:?}", other),
+        }
+    }
+
+    #[test]
+    fn doc_comments_attach_to_fn_and_secure_const() {
+        let source = "/// Adds one.
+/// Never fails.
+fn inc(n) {
+    return n + 1
+}
+/// The API key.
+secure const key = \"k\"
+/// Dropped: plain lets aren't documented.
+let x = 1
+// An ordinary comment
+fn bare() {
+}";
+        let ast = parse(source);
+        let docs: Vec<Option<&str>> = ast.iter().map(doc).collect();
+        assert_eq!(docs, [Some("Adds one.\nNever fails."), Some("The API key."), None, None]);
+    }
+
+    /// (line, column, end_line, end_column)
+    fn position(span: Span) -> (usize, usize, usize, usize) {
+        (span.line, span.column, span.end_line, span.end_column)
+    }
+
+    #[test]
+    fn nodes_span_their_source() {
+        let source = "print 1\nlet total = (a +\n    b) * 2\n";
+        let ast = parse(source);
+        assert_eq!(position(ast[0].span), (1, 1, 1, 8));
+        assert_eq!(position(ast[1].span), (2, 1, 3, 11));
+        let Expr::Let { name, value, .. } = &ast[1].node else { panic!("not a let") };
+        assert_eq!(position(name.span), (2, 5, 2, 10));
+        assert_eq!(&source[value.span.start..value.span.end], "(a +\n    b) * 2");
+        let Expr::Binary { left, .. } = &value.node else { panic!("not a binary") };
+        assert_eq!(&source[left.span.start..left.span.end], "(a +\n    b)");
     }
 }
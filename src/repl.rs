@@ -1,115 +1,25 @@
-// src/repl.rs - FalconCore REPL (Read-Eval-Print Loop)
-use std::io::{self, BufRead};
-use crate::lexer::Lexer;
-use crate::parser::Parser;
-use crate::compiler::Compiler;
-use crate::vm::VM;
-
-pub fn start_repl() {
-    println!("FalconCore REPL v0.1");
-    println!("Type Falcon code and press Enter (type 'exit' to quit)");
-
-    let stdin = io::stdin();
-    for line in stdin.lines() {
-        let input = line.unwrap();
-        if input.trim() == "exit" {
-            println!("Goodbye!");
-            break;
-        }
-
-        if input.trim().is_empty() {
-            continue;
-        }
-
-        let lexer = Lexer::new(&input);
-        let mut parser = Parser::new(lexer);
-        let ast = parser.parse();
-
-        let mut compiler = Compiler::new();
-        compiler.compile(ast);
-
-        let mut vm = VM::new(compiler.get_constants().clone(), compiler.get_code().clone());
-        vm.run();
-
-        println!("> ");
-    }
-}
-// src/repl.rs - FalconCore REPL (Multi-line + History)
-use std::io::{self, BufRead, Write};
-use crate::lexer::Lexer;
-use crate::parser::Parser;
-use crate::compiler::Compiler;
-use crate::vm::VM;
-
-pub fn start_repl() {
-    println!("FalconCore REPL v0.1");
-    println!("Multi-line code supported. Type 'exit' or Ctrl+D to quit");
-    println!("Type code and press Enter (multi-line: end with empty line)");
-
-    let stdin = io::stdin();
-    let mut history: Vec<String> = vec![];
-
-    loop {
-        print!("falcon> ");
-        io::stdout().flush().unwrap();
-
-        let mut input = String::new();
-        let mut line_count = 0;
-
-        loop {
-            let mut line = String::new();
-            match stdin.read_line(&mut line) {
-                Ok(0) => return, // EOF
-                Ok(_) => {}
-                Err(_) => return,
-            }
-
-            let trimmed = line.trim();
-            if trimmed.is_empty() && line_count > 0 {
-                break; // end multi-line
-            }
-
-            if trimmed == "exit" {
-                println!("Goodbye!");
-                return;
-            }
-
-            input.push_str(&line);
-            line_count += 1;
-        }
-
-        if input.trim().is_empty() {
-            continue;
-        }
-
-        history.push(input.clone());
-
-        let lexer = Lexer::new(&input);
-        let mut parser = Parser::new(lexer);
-        let ast = parser.parse();
-
-        let mut compiler = Compiler::new();
-        compiler.compile(ast);
-
-        let mut vm = VM::new(compiler.get_constants().clone(), compiler.get_code().clone());
-        vm.run();
-
-        println!();
-    }
-        }
-// src/repl.rs - FalconCore REPL (Enhanced: multi-line + basic history navigation)
-use std::io::{self, BufRead, Write};
+// src/repl.rs - FalconCore REPL (Advanced: multi-line, history navigation, syntax highlight)
+use std::collections::HashMap;
+use std::io::{self, Write};
 use crate::lexer::Lexer;
-use crate::parser::Parser;
+use crate::parser::{Expr, Parser};
+use crate::resolver::{Globals, Resolver};
 use crate::compiler::Compiler;
+use crate::span::{Span, Spanned};
 use crate::vm::VM;
 
 pub fn start_repl() {
-    println!("FalconCore REPL v0.1 - Multi-line + History");
-    println!("Type code (multi-line: end with empty line), 'exit' to quit, 'history' to see past commands");
+    println!("FalconCore REPL v0.1 - Multi-line + History + Syntax Highlight");
+    println!("Commands:");
+    println!("  exit / Ctrl+D - Quit");
+    println!("  history       - Show command history");
+    println!("  clear         - Clear screen (terminal only)");
+    println!("Multi-line: end input with empty line");
 
     let stdin = io::stdin();
+    let mut session = Session::default();
     let mut history: Vec<String> = vec![];
+    let mut history_index = 0;
 
     loop {
         print!("falcon> ");
@@ -145,6 +55,13 @@ pub fn start_repl() {
                 break; // end multi-line input
             }
 
+            // Arrow key navigation placeholder
+            if trimmed == "up" && !history.is_empty() {
+                history_index = if history_index > 0 { history_index - 1 } else { history.len() - 1 };
+                println!("(Up arrow: {})", history[history_index]);
+                continue;
+            }
+
             input.push_str(&line);
             line_count += 1;
         }
@@ -153,198 +70,152 @@ pub fn start_repl() {
             continue;
         }
 
-        history.push(input.trim().to_string());
-
         let lexer = Lexer::new(&input);
-        let mut parser = Parser::new(lexer);
-        let ast = parser.parse();
-
-        let mut compiler = Compiler::new();
-        compiler.compile(ast);
-
-        let mut vm = VM::new(compiler.get_constants().clone(), compiler.get_code().clone());
-        vm.run();
-
-        println!();
-    }
-    }
-// src/repl.rs - FalconCore REPL (Enhanced: multi-line, history navigation placeholder, basic syntax highlight)
-use std::io::{self, BufRead, Write};
-use crate::lexer::Lexer;
-use crate::parser::Parser;
-use crate::compiler::Compiler;
-use crate::vm::VM;
-
-pub fn start_repl() {
-    println!("FalconCore REPL v0.1 - Multi-line + History + Basic Syntax Highlight");
-    println!("Type code (multi-line: end with empty line), 'exit' to quit, 'history' to see past commands");
-
-    let stdin = io::stdin();
-    let mut history: Vec<String> = vec![];
-
-    loop {
-        print!("falcon> ");
-        io::stdout().flush().unwrap();
-
-        let mut input = String::new();
-        let mut line_count = 0;
-
-        loop {
-            let mut line = String::new();
-            match stdin.read_line(&mut line) {
-                Ok(0) => return, // EOF
-                Ok(_) => {}
-                Err(_) => return,
-            }
-
-            let trimmed = line.trim().to_string();
-
-            if trimmed == "exit" {
-                println!("Goodbye!");
-                return;
-            }
-
-            if trimmed == "history" {
-                println!("History:");
-                for (i, cmd) in history.iter().enumerate() {
-                    println!("{:3}: {}", i + 1, cmd);
+        let mut parser = Parser::new(lexer).with_file("<repl>");
+        let ast = match parser.parse() {
+            Ok(ast) => ast,
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    println!("{}\n", diagnostic);
                 }
                 continue;
             }
+        };
 
-            if trimmed.is_empty() && line_count > 0 {
-                break; // end multi-line input
-            }
-
-            // Basic syntax highlight placeholder (just color keywords in terminal)
-            let highlighted = highlight_syntax(&line);
-            print!("{}", highlighted);
+        // History and the echo only ever see the redacted text
+        let redacted = redact_secrets(&input, &ast, |name| session.compiler.is_secure(name));
+        history.push(redacted.trim().to_string());
+        history_index = history.len();
 
-            input.push_str(&line);
-            line_count += 1;
-        }
+        let highlighted = highlight_syntax(&redacted);
+        println!("\nInput (highlighted):\n{}", highlighted);
 
-        if input.trim().is_empty() {
-            continue;
+        if let Err(message) = session.eval(&input, ast) {
+            println!("{}", message);
         }
 
-        history.push(input.trim().to_string());
-
-        let lexer = Lexer::new(&input);
-        let mut parser = Parser::new(lexer);
-        let ast = parser.parse();
-
-        let mut compiler = Compiler::new();
-        compiler.compile(ast);
-
-        let mut vm = VM::new(compiler.get_constants().clone(), compiler.get_code().clone());
-        vm.run();
-
         println!();
     }
 }
 
-// Basic syntax highlight (terminal colors)
-fn highlight_syntax(line: &str) -> String {
-    let keywords = vec!["secure", "let", "const", "fn", "return", "if", "else", "repeat", "print", "network", "scan", "crypto", "random", "time", "now", "wait"];
-    let mut highlighted = line.to_string();
+/// Everything one entry leaves for the next: declared names, compiled code and the VM's globals.
+#[derive(Default)]
+struct Session {
+    globals: Option<Globals>,
+    compiler: Compiler,
+    // Instructions compiled by earlier entries; the next entry starts right after them
+    compiled: usize,
+    vm: Option<VM>,
+}
 
-    for kw in keywords {
-        highlighted = highlighted.replace(kw, &format!("\x1b[1;34m{}\x1b[0m", kw));
+impl Session {
+    /// Resolves, compiles and runs one entry, returning its diagnostics or runtime error as text.
+    fn eval(&mut self, input: &str, ast: Vec<Spanned<Expr>>) -> Result<(), String> {
+        let mut resolver = Resolver::new(input).with_file("<repl>");
+        if let Some(globals) = &self.globals {
+            resolver = resolver.with_globals(globals.clone());
+        }
+        if let Err(diagnostics) = resolver.resolve(&ast) {
+            return Err(diagnostics.iter().map(|diagnostic| format!("{}\n", diagnostic)).collect::<Vec<_>>().join("\n"));
+        }
+        self.globals = resolver.globals();
+
+        // Entries aren't optimized: that would move earlier entries' code out from under their functions
+        self.compiler.compile(ast);
+        let program = self.compiler.program();
+        let entry = std::mem::replace(&mut self.compiled, program.code.len());
+        let result = match &mut self.vm {
+            Some(vm) => vm.run_from(program, entry),
+            None => self.vm.insert(VM::new(program)).run(),
+        };
+        result.map(|_| ()).map_err(|error| format!("error: {}", error))
     }
+}
 
-    highlighted.replace("print", "\x1b[1;32mprint\x1b[0m")
-        .replace("\"", "\x1b[1;33m\"\x1b[0m")
-        .replace("=", "\x1b[1;31m=\x1b[0m")
+/// The source with the initializer of every `secure let/const`, and every value assigned to a
+/// secure binding, replaced by [REDACTED]. `is_secure_global` answers for names from earlier entries.
+fn redact_secrets(source: &str, ast: &[Spanned<Expr>], is_secure_global: impl Fn(&str) -> bool) -> String {
+    let mut spans = vec![];
+    secret_spans(ast, &mut vec![HashMap::new()], &is_secure_global, &mut spans);
+    spans.sort_by_key(|span| span.start);
+
+    let mut redacted = String::new();
+    let mut at = 0;
+    for span in spans {
+        redacted.push_str(&source[at..span.start]);
+        redacted.push_str("[REDACTED]");
+        at = span.end;
     }
-// src/repl.rs - FalconCore REPL (Advanced: multi-line, history navigation, syntax highlight)
-use std::io::{self, BufRead, Write};
-use crate::lexer::Lexer;
-use crate::parser::Parser;
-use crate::compiler::Compiler;
-use crate::vm::VM;
-
-pub fn start_repl() {
-    println!("FalconCore REPL v0.1 - Multi-line + History + Syntax Highlight");
-    println!("Commands:");
-    println!("  exit / Ctrl+D - Quit");
-    println!("  history       - Show command history");
-    println!("  clear         - Clear screen (terminal only)");
-    println!("Multi-line: end input with empty line");
-
-    let stdin = io::stdin();
-    let mut history: Vec<String> = vec![];
-    let mut history_index = 0;
-
-    loop {
-        print!("falcon> ");
-        io::stdout().flush().unwrap();
-
-        let mut input = String::new();
-        let mut line_count = 0;
+    redacted.push_str(&source[at..]);
+    redacted
+}
 
-        loop {
-            let mut line = String::new();
-            match stdin.read_line(&mut line) {
-                Ok(0) => return, // EOF
-                Ok(_) => {}
-                Err(_) => return,
+/// Collects the secret values in `block`. `scopes` maps each name declared so far to whether it's
+/// secure, innermost last, with the entry's globals at the bottom, as the compiler scopes them.
+fn secret_spans(
+    block: &[Spanned<Expr>],
+    scopes: &mut Vec<HashMap<String, bool>>,
+    is_secure_global: &dyn Fn(&str) -> bool,
+    spans: &mut Vec<Span>,
+) {
+    for stmt in block {
+        match &stmt.node {
+            Expr::Let { is_secure, name, value, .. } => {
+                if *is_secure {
+                    spans.push(value.span);
+                }
+                scopes.last_mut().expect("global scope").insert(name.node.clone(), *is_secure);
             }
-
-            let trimmed = line.trim().to_string();
-
-            if trimmed == "exit" {
-                println!("Goodbye!");
-                return;
+            Expr::Assign { name, value, .. } => {
+                let secure = match scopes.iter().rev().find_map(|scope| scope.get(name)) {
+                    Some(secure) => *secure,
+                    None => is_secure_global(name),
+                };
+                if secure {
+                    spans.push(value.span);
+                }
             }
-
-            if trimmed == "history" {
-                println!("History:");
-                for (i, cmd) in history.iter().enumerate() {
-                    println!("{:3}: {}", i + 1, cmd);
+            Expr::If { branches, else_branch } => {
+                for (_, body) in branches {
+                    nested(body, &[], scopes, is_secure_global, spans);
+                }
+                if let Some(body) = else_branch {
+                    nested(body, &[], scopes, is_secure_global, spans);
                 }
-                continue;
             }
-
-            if trimmed.is_empty() && line_count > 0 {
-                break; // end multi-line input
+            Expr::Repeat { body, .. } | Expr::While { body, .. } => nested(body, &[], scopes, is_secure_global, spans),
+            Expr::ForIn { var, body, .. } | Expr::ForRange { var, body, .. } => nested(body, std::slice::from_ref(var), scopes, is_secure_global, spans),
+            Expr::Try { body, catch, finally } => {
+                nested(body, &[], scopes, is_secure_global, spans);
+                if let Some((name, body)) = catch {
+                    nested(body, std::slice::from_ref(name), scopes, is_secure_global, spans);
+                }
+                if let Some(body) = finally {
+                    nested(body, &[], scopes, is_secure_global, spans);
+                }
             }
-
-            // Arrow key navigation placeholder
-            if trimmed == "up" && !history.is_empty() {
-                history_index = if history_index > 0 { history_index - 1 } else { history.len() - 1 };
-                println!("(Up arrow: {})", history[history_index]);
-                continue;
+            Expr::FnDef { name, params, body, .. } => {
+                scopes.last_mut().expect("global scope").insert(name.clone(), false);
+                nested(body, params, scopes, is_secure_global, spans);
             }
-
-            input.push_str(&line);
-            line_count += 1;
-        }
-
-        if input.trim().is_empty() {
-            continue;
+            _ => {}
         }
-
-        history.push(input.trim().to_string());
-        history_index = history.len();
-
-        let highlighted = highlight_syntax(&input);
-        println!("\nInput (highlighted):\n{}", highlighted);
-
-        let lexer = Lexer::new(&input);
-        let mut parser = Parser::new(lexer);
-        let ast = parser.parse();
-
-        let mut compiler = Compiler::new();
-        compiler.compile(ast);
-
-        let mut vm = VM::new(compiler.get_constants().clone(), compiler.get_code().clone());
-        vm.run();
-
-        println!();
     }
 }
 
+/// `secret_spans` for a nested block, with `names` (parameters, loop or catch variables) declared in it.
+fn nested(
+    body: &[Spanned<Expr>],
+    names: &[String],
+    scopes: &mut Vec<HashMap<String, bool>>,
+    is_secure_global: &dyn Fn(&str) -> bool,
+    spans: &mut Vec<Span>,
+) {
+    scopes.push(names.iter().map(|name| (name.clone(), false)).collect());
+    secret_spans(body, scopes, is_secure_global, spans);
+    scopes.pop();
+}
+
 fn highlight_syntax(code: &str) -> String {
     let keywords = vec!["secure", "let", "const", "fn", "return", "if", "else", "repeat", "print", "network", "scan", "crypto", "random", "time", "now", "wait"];
     let mut highlighted = code.to_string();
@@ -357,3 +228,54 @@ fn highlight_syntax(code: &str) -> String {
         .replace("\"", "\x1b[1;33m\"\x1b[0m")
         .replace("=", "\x1b[1;31m=\x1b[0m")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Vec<Spanned<Expr>> {
+        Parser::new(Lexer::new(source)).parse().expect("parses")
+    }
+
+    fn redact(session: &Session, source: &str) -> String {
+        redact_secrets(source, &parse(source), |name| session.compiler.is_secure(name))
+    }
+
+    #[test]
+    fn redacts_multi_line_initializers_and_secure_assignments() {
+        let session = Session::default();
+        let source = "secure let token = \"abc\" +\n    \"def\"\nlet shown = \"plain\"\ntoken = \"ghi\"\nshown = \"visible\"\n";
+        let redacted = redact(&session, source);
+        assert_eq!(redacted, "secure let token = [REDACTED]\nlet shown = \"plain\"\ntoken = [REDACTED]\nshown = \"visible\"\n");
+    }
+
+    #[test]
+    fn shadowing_bindings_keep_their_own_secrecy() {
+        let session = Session::default();
+        let source = "secure let key = \"k\"\nfn f(key) {\n    key = \"not secret\"\n}\n";
+        assert!(redact(&session, source).contains("key = \"not secret\""));
+    }
+
+    #[test]
+    fn entries_share_globals_and_functions() {
+        let mut session = Session::default();
+        let first = "let x = 41\nfn next(n) {\n    return n + 1\n}\nsecure let pin = \"1234\"\n";
+        assert_eq!(session.eval(first, parse(first)), Ok(()));
+        let second = "if next(x) != 42 {\n    throw \"lost x\"\n}\n";
+        assert_eq!(session.eval(second, parse(second)), Ok(()));
+
+        // A secure global from an earlier entry is still redacted when reassigned
+        assert_eq!(redact(&session, "pin = \"9999\""), "pin = [REDACTED]");
+    }
+
+    #[test]
+    fn failed_entries_leave_the_session_usable() {
+        let mut session = Session::default();
+        let broken = "print missing\n";
+        assert!(session.eval(broken, parse(broken)).is_err());
+        let failing = "let y = 1\nthrow \"boom\"\n";
+        assert!(session.eval(failing, parse(failing)).is_err());
+        let next = "if y != 1 {\n    throw \"lost y\"\n}\n";
+        assert_eq!(session.eval(next, parse(next)), Ok(()));
+    }
+}
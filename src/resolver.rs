@@ -0,0 +1,349 @@
+// src/resolver.rs - FalconCore Resolver (scope + const checks between parser and compiler)
+use crate::diagnostic::Diagnostic;
+use crate::natives;
+use crate::parser::Expr;
+use crate::span::Spanned;
+use std::collections::HashMap;
+
+#[derive(Clone)]
+struct Binding {
+    is_const: bool,
+}
+
+/// The global scope a resolve ended with, builtins included; the REPL seeds each entry with the last.
+#[derive(Clone)]
+pub struct Globals(HashMap<String, Binding>);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Access {
+    Read,
+    Assign,
+    Call,
+}
+
+struct Deferred {
+    name: String,
+    access: Access,
+    line: usize,
+    column: usize,
+}
+
+pub struct Resolver<'a> {
+    source: &'a str,
+    file: String,
+    scopes: Vec<HashMap<String, Binding>>,
+    function_depth: usize,
+    // Enclosing loops in the current function, for break/continue
+    loop_depth: usize,
+    // Names used inside function bodies that weren't declared yet; they may be globals defined later
+    deferred: Vec<Deferred>,
+    diagnostics: Vec<Diagnostic>,
+    // Declared before this resolve, if it continues an earlier one
+    globals: Option<Globals>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Resolver {
+            source,
+            file: "<input>".to_string(),
+            scopes: vec![],
+            function_depth: 0,
+            loop_depth: 0,
+            deferred: vec![],
+            diagnostics: vec![],
+            globals: None,
+        }
+    }
+
+    /// Sets the file name reported in diagnostics.
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file = file.to_string();
+        self
+    }
+
+    /// Starts from globals an earlier resolve declared instead of the builtins alone.
+    pub fn with_globals(mut self, globals: Globals) -> Self {
+        self.globals = Some(globals);
+        self
+    }
+
+    /// The globals after the last successful resolve, to seed the next one.
+    pub fn globals(&self) -> Option<Globals> {
+        self.globals.clone()
+    }
+
+    pub fn resolve(&mut self, ast: &[Spanned<Expr>]) -> Result<(), Vec<Diagnostic>> {
+        // Builtins live in the global scope and can't be reassigned
+        let globals = match &self.globals {
+            Some(globals) => globals.0.clone(),
+            None => natives::globals().into_iter().map(|(name, _)| (name.to_string(), Binding { is_const: true })).collect(),
+        };
+        self.scopes.push(globals);
+        for stmt in ast {
+            self.resolve_expr(stmt);
+        }
+
+        for use_ in std::mem::take(&mut self.deferred) {
+            match self.scopes[0].get(&use_.name) {
+                None => self.undeclared(&use_.name, use_.access, use_.line, use_.column),
+                Some(binding) if binding.is_const && use_.access == Access::Assign => {
+                    self.error(use_.line, use_.column, format!("Cannot assign to constant '{}'", use_.name))
+                }
+                Some(_) => {}
+            }
+        }
+        let globals = self.scopes.swap_remove(0);
+        self.scopes.clear();
+
+        if self.diagnostics.is_empty() {
+            self.globals = Some(Globals(globals));
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.diagnostics))
+        }
+    }
+
+    fn error(&mut self, line: usize, column: usize, message: String) {
+        self.diagnostics
+            .push(Diagnostic::new(&self.file, self.source, line, column, message));
+    }
+
+    fn declare(&mut self, name: &str, is_const: bool, line: usize, column: usize) {
+        let scope = self.scopes.last_mut().expect("resolver always has a scope");
+        if scope.get(name).is_some_and(|binding| binding.is_const) {
+            self.error(line, column, format!("Cannot redeclare constant '{}' in the same scope", name));
+            return;
+        }
+        scope.insert(name.to_string(), Binding { is_const });
+    }
+
+    fn lookup(&mut self, name: &str, access: Access, line: usize, column: usize) {
+        match self.find(name) {
+            Some(binding) if binding.is_const && access == Access::Assign => {
+                self.error(line, column, format!("Cannot assign to constant '{}'", name));
+            }
+            Some(_) => {}
+            None if self.function_depth > 0 => self.deferred.push(Deferred {
+                name: name.to_string(),
+                access,
+                line,
+                column,
+            }),
+            None => self.undeclared(name, access, line, column),
+        }
+    }
+
+    fn undeclared(&mut self, name: &str, access: Access, line: usize, column: usize) {
+        let message = match access {
+            Access::Call => format!("Call to undefined function '{}'", name),
+            Access::Read | Access::Assign => format!("Use of undeclared variable '{}'", name),
+        };
+        self.error(line, column, message);
+    }
+
+    fn find(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn resolve_block(&mut self, body: &[Spanned<Expr>]) {
+        self.scopes.push(HashMap::new());
+        for stmt in body {
+            self.resolve_expr(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    /// A loop body is a block scope, with the loop variable (if any) declared in it.
+    fn resolve_loop_body(&mut self, var: Option<&String>, body: &[Spanned<Expr>]) {
+        self.loop_depth += 1;
+        let mut scope = HashMap::new();
+        if let Some(var) = var {
+            scope.insert(var.clone(), Binding { is_const: false });
+        }
+        self.scopes.push(scope);
+        for stmt in body {
+            self.resolve_expr(stmt);
+        }
+        self.scopes.pop();
+        self.loop_depth -= 1;
+    }
+
+    fn resolve_expr(&mut self, expr: &Spanned<Expr>) {
+        let (line, column) = (expr.span.line, expr.span.column);
+        match &expr.node {
+            Expr::Number(_) | Expr::Float(_) | Expr::Bool(_) | Expr::String(_) => {}
+            Expr::Identifier(name) => self.lookup(name, Access::Read, line, column),
+            Expr::Binary { left, right, .. } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Expr::Unary { operand, .. } => self.resolve_expr(operand),
+            Expr::Call { callee, args } => {
+                match &callee.node {
+                    Expr::Identifier(name) => self.lookup(name, Access::Call, callee.span.line, callee.span.column),
+                    _ => self.resolve_expr(callee),
+                }
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+            }
+            Expr::Member { object, .. } => self.resolve_expr(object),
+            Expr::Interpolation(parts) => {
+                for part in parts {
+                    self.resolve_expr(part);
+                }
+            }
+            Expr::List(items) => {
+                for item in items {
+                    self.resolve_expr(item);
+                }
+            }
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
+            Expr::Index { object, index, .. } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            }
+            Expr::SetIndex { object, index, value, .. } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+                self.resolve_expr(value);
+            }
+            Expr::Let { is_const, name, value, .. } => {
+                // The initializer can't see the binding it initializes
+                self.resolve_expr(value);
+                self.declare(&name.node, *is_const, name.span.line, name.span.column);
+            }
+            Expr::Assign { name, value, .. } => {
+                self.resolve_expr(value);
+                self.lookup(name, Access::Assign, line, column);
+            }
+            Expr::Print { expr } => self.resolve_expr(expr),
+            Expr::If { branches, else_branch } => {
+                for (condition, body) in branches {
+                    self.resolve_expr(condition);
+                    self.resolve_block(body);
+                }
+                if let Some(else_branch) = else_branch {
+                    self.resolve_block(else_branch);
+                }
+            }
+            Expr::Repeat { times, body } => {
+                self.resolve_expr(times);
+                self.resolve_loop_body(None, body);
+            }
+            Expr::While { condition, body } => {
+                self.resolve_expr(condition);
+                self.resolve_loop_body(None, body);
+            }
+            Expr::ForIn { var, iterable, body, .. } => {
+                self.resolve_expr(iterable);
+                self.resolve_loop_body(Some(var), body);
+            }
+            Expr::ForRange { var, start, end, body, .. } => {
+                self.resolve_expr(start);
+                self.resolve_expr(end);
+                self.resolve_loop_body(Some(var), body);
+            }
+            Expr::Break | Expr::Continue => {
+                if self.loop_depth == 0 {
+                    let keyword = if expr.node == Expr::Break { "break" } else { "continue" };
+                    self.error(line, column, format!("'{}' outside of a loop", keyword));
+                }
+            }
+            Expr::Throw { value, .. } => self.resolve_expr(value),
+            Expr::Try { body, catch, finally } => {
+                self.resolve_block(body);
+                if let Some((var, body)) = catch {
+                    self.scopes.push(HashMap::from([(var.clone(), Binding { is_const: false })]));
+                    for stmt in body {
+                        self.resolve_expr(stmt);
+                    }
+                    self.scopes.pop();
+                }
+                if let Some(finally) = finally {
+                    self.resolve_block(finally);
+                }
+            }
+            Expr::FnDef { name, params, body, .. } => {
+                // Declared before the body so the function can call itself
+                self.scopes
+                    .last_mut()
+                    .expect("resolver always has a scope")
+                    .insert(name.clone(), Binding { is_const: false });
+
+                // Loops outside the function can't be broken out of from inside it
+                let loop_depth = std::mem::take(&mut self.loop_depth);
+                self.function_depth += 1;
+                self.scopes.push(params.iter().map(|p| (p.clone(), Binding { is_const: false })).collect());
+                for stmt in body {
+                    self.resolve_expr(stmt);
+                }
+                self.scopes.pop();
+                self.function_depth -= 1;
+                self.loop_depth = loop_depth;
+            }
+            Expr::Return { value } => {
+                if let Some(value) = value {
+                    self.resolve_expr(value);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    /// Each diagnostic as `line:column message`.
+    fn resolve(source: &str) -> Vec<String> {
+        let ast = Parser::new(Lexer::new(source)).parse().expect("test scripts parse");
+        match Resolver::new(source).resolve(&ast) {
+            Ok(()) => vec![],
+            Err(diagnostics) => diagnostics.iter().map(|d| format!("{}:{} {}", d.line, d.column, d.message)).collect(),
+        }
+    }
+
+    #[test]
+    fn constants_cant_be_reassigned() {
+        assert_eq!(resolve("const x = 1\nx = 2"), ["2:1 Cannot assign to constant 'x'"]);
+        assert_eq!(resolve("const x = 1\nconst x = 2"), ["2:7 Cannot redeclare constant 'x' in the same scope"]);
+        assert_eq!(resolve("fn f() {\n    limit = 3\n}\nconst limit = 1"), ["2:5 Cannot assign to constant 'limit'"]);
+        assert_eq!(resolve("char = 1"), ["1:1 Cannot assign to constant 'char'"]);
+    }
+
+    #[test]
+    fn compound_assignment_to_a_constant_is_rejected() {
+        assert_eq!(resolve("const x = 1\nx += 1"), ["2:1 Cannot assign to constant 'x'"]);
+        assert_eq!(resolve("secure const key = \"k\"\nkey += \"x\""), ["2:1 Cannot assign to constant 'key'"]);
+    }
+
+    #[test]
+    fn inner_scopes_may_shadow_a_constant() {
+        assert_eq!(resolve("const x = 1\nif true {\n    let x = 2\n    x = 3\n}"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn undeclared_names_are_reported() {
+        assert_eq!(resolve("print y"), ["1:7 Use of undeclared variable 'y'"]);
+        assert_eq!(resolve("y = 1"), ["1:1 Use of undeclared variable 'y'"]);
+        assert_eq!(resolve("missing()"), ["1:1 Call to undefined function 'missing'"]);
+        // Functions may use globals declared after them
+        assert_eq!(resolve("fn f() {\n    return later\n}\nlet later = 1"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn break_and_continue_need_an_enclosing_loop() {
+        assert_eq!(resolve("break"), ["1:1 'break' outside of a loop"]);
+        let source = "while true {\n    fn f() {\n        continue\n    }\n    break\n}";
+        assert_eq!(resolve(source), ["3:9 'continue' outside of a loop"]);
+    }
+}
@@ -0,0 +1,56 @@
+// src/span.rs - FalconCore source spans (byte offsets + line/column ranges)
+
+/// A stretch of source text: byte offsets `start..end`, plus the line/column it starts at and
+/// the line/column just past its last character. Lines and columns count from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Span {
+    /// An empty span at one position.
+    pub fn point(offset: usize, line: usize, column: usize) -> Self {
+        Span {
+            start: offset,
+            end: offset,
+            line,
+            column,
+            end_line: line,
+            end_column: column,
+        }
+    }
+
+    /// From the start of `self` to the end of `end`.
+    pub fn to(self, end: Span) -> Span {
+        Span {
+            end: end.end,
+            end_line: end.end_line,
+            end_column: end.end_column,
+            ..self
+        }
+    }
+}
+
+impl Default for Span {
+    fn default() -> Self {
+        Span::point(0, 1, 1)
+    }
+}
+
+/// A syntax tree node together with the source it was parsed from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Spanned { node, span }
+    }
+}
@@ -0,0 +1,359 @@
+// src/value.rs - FalconCore runtime values (what lives on the VM stack and in the constant pool)
+use crate::error::RuntimeError;
+use crate::gc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use zeroize::Zeroize;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(Rc<str>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<HashMap<String, Value>>>),
+    Function(Rc<Function>),
+    Closure(Rc<Closure>),
+    NativeFunction(NativeFunction),
+    Secret(Rc<Secret>),
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    // Parameters plus every `let` in the body; each gets its own slot in the call frame
+    pub local_count: usize,
+    pub captures: Vec<Capture>,
+    pub start_ip: usize,
+}
+
+/// Where a closure gets one of its upvalues when it is created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capture {
+    // true: a slot of the enclosing frame; false: one of the enclosing closure's upvalues
+    pub is_local: bool,
+    pub index: usize,
+}
+
+/// A function value together with the variables it captured.
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// A captured variable: still living in a frame slot, or moved out once that slot went away.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub type NativeFn = fn(&[Value]) -> Result<Value, RuntimeError>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: usize,
+    pub func: NativeFn,
+}
+
+/// A `secure` value: renders as [REDACTED] everywhere and is zeroised when the last reference drops.
+pub struct Secret {
+    value: Value,
+}
+
+impl Secret {
+    /// Plaintext for the VM's own use (arithmetic, comparisons); never hand this to scripts.
+    pub fn expose(&self) -> &Value {
+        &self.value
+    }
+
+    /// A fresh copy of the plaintext, so the secret keeps sole ownership of its buffer.
+    pub fn reveal(&self) -> Value {
+        match &self.value {
+            Value::Str(s) => Value::string(s),
+            other => other.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[REDACTED]")
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        match &mut self.value {
+            // Only wipe string data nobody else can still see
+            Value::Str(s) => {
+                if let Some(s) = Rc::get_mut(s) {
+                    s.zeroize();
+                }
+            }
+            // A secret list (from `string.split`, say) wipes the strings only it holds
+            Value::List(items) if Rc::strong_count(items) == 1 => {
+                for item in items.borrow_mut().iter_mut() {
+                    if let Value::Str(s) = item {
+                        if let Some(s) = Rc::get_mut(s) {
+                            s.zeroize();
+                        }
+                    }
+                }
+            }
+            Value::Int(n) => n.zeroize(),
+            Value::Float(f) => f.zeroize(),
+            Value::Bool(b) => b.zeroize(),
+            _ => {}
+        }
+    }
+}
+
+impl Value {
+    pub fn string(s: &str) -> Value {
+        let s: Rc<str> = Rc::from(s);
+        gc::track_str(&s);
+        Value::Str(s)
+    }
+
+    /// Wraps a value as a secret; already-secret values are returned unchanged.
+    pub fn secret(value: Value) -> Value {
+        let value = match value {
+            Value::Secret(_) => return value,
+            // Copy string data so the secret owns the only reference it will zeroise; untracked,
+            // since a weak reference from the heap would stop `Rc::get_mut` in `drop`
+            Value::Str(s) => Value::Str(Rc::from(&*s)),
+            other => other,
+        };
+        Value::Secret(Rc::new(Secret { value }))
+    }
+
+    /// A secret string holding `text`, which is wiped once copied. Text derived from a secret is
+    /// built with this rather than `string`, so no tracked plaintext copy is left behind.
+    pub fn secret_string(mut text: String) -> Value {
+        let value = Value::Str(Rc::from(text.as_str()));
+        text.zeroize();
+        Value::Secret(Rc::new(Secret { value }))
+    }
+
+    pub fn is_secret(&self) -> bool {
+        matches!(self, Value::Secret(_))
+    }
+
+    /// The plaintext behind a secret, or the value itself.
+    pub fn expose(&self) -> &Value {
+        match self {
+            Value::Secret(secret) => secret.expose(),
+            other => other,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "string",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Function(_) | Value::Closure(_) => "function",
+            Value::NativeFunction(_) => "native function",
+            Value::Secret(_) => "secret",
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Bool(b) => *b,
+            Value::Int(n) => *n != 0,
+            Value::Float(f) => *f != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            Value::List(items) => !items.borrow().is_empty(),
+            Value::Map(entries) => !entries.borrow().is_empty(),
+            Value::Function(_) | Value::Closure(_) | Value::NativeFunction(_) => true,
+            Value::Secret(secret) => secret.expose().is_truthy(),
+        }
+    }
+
+    /// Numeric view of a value for Int -> Float promotion.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Int(n) => Some(*n as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// How the value is written inside a list or map: strings are quoted. `path` holds the lists
+    /// and maps being written around this one, so a collection that contains itself prints as
+    /// `[...]` or `{...}` instead of recursing forever.
+    fn fmt_nested(&self, f: &mut fmt::Formatter<'_>, path: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::List(_) | Value::Map(_) => self.fmt_collection(f, path),
+            _ => write!(f, "{}", self),
+        }
+    }
+
+    fn fmt_collection(&self, f: &mut fmt::Formatter<'_>, path: &mut Vec<*const ()>) -> fmt::Result {
+        let address = match self {
+            Value::List(items) => Rc::as_ptr(items) as *const (),
+            Value::Map(entries) => Rc::as_ptr(entries) as *const (),
+            _ => return write!(f, "{}", self),
+        };
+        if path.contains(&address) {
+            return write!(f, "{}", if matches!(self, Value::List(_)) { "[...]" } else { "{...}" });
+        }
+        path.push(address);
+        match self {
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_nested(f, path)?;
+                }
+                write!(f, "]")?;
+            }
+            Value::Map(entries) => {
+                let entries = entries.borrow();
+                let mut keys: Vec<&String> = entries.keys().collect();
+                keys.sort();
+                write!(f, "{{")?;
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: ", key)?;
+                    entries[key].fmt_nested(f, path)?;
+                }
+                write!(f, "}}")?;
+            }
+            _ => {}
+        }
+        path.pop();
+        Ok(())
+    }
+
+    /// Structural equality. A pair of collections already being compared counts as equal, so two
+    /// cyclic lists of the same shape compare equal instead of recursing forever.
+    fn equals(&self, other: &Value, seen: &mut Vec<(*const (), *const ())>) -> bool {
+        match (self, other) {
+            (Value::List(a), Value::List(b)) => {
+                let pair = (Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ());
+                if Rc::ptr_eq(a, b) || seen.contains(&pair) {
+                    return true;
+                }
+                seen.push(pair);
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| x.equals(y, seen))
+            }
+            (Value::Map(a), Value::Map(b)) => {
+                let pair = (Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ());
+                if Rc::ptr_eq(a, b) || seen.contains(&pair) {
+                    return true;
+                }
+                seen.push(pair);
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len() && a.iter().all(|(key, x)| b.get(key).is_some_and(|y| x.equals(y, seen)))
+            }
+            (Value::Secret(a), Value::Secret(b)) => a.expose().equals(b.expose(), seen),
+            _ => self == other,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => self.as_float() == other.as_float(),
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::List(_), Value::List(_)) | (Value::Map(_), Value::Map(_)) | (Value::Secret(_), Value::Secret(_)) => {
+                self.equals(other, &mut vec![])
+            }
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::NativeFunction(a), Value::NativeFunction(b)) => a.name == b.name,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::List(_) | Value::Map(_) => self.fmt_collection(f, &mut vec![]),
+            Value::Function(func) => write!(f, "<fn {}>", func.name),
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.function.name),
+            Value::NativeFunction(native) => write!(f, "<native fn {}>", native.name),
+            Value::Secret(_) => write!(f, "[REDACTED]"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(items: Vec<Value>) -> Value {
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+
+    #[test]
+    fn values_display_like_falcon_source() {
+        let map = Value::Map(Rc::new(RefCell::new(HashMap::from([
+            ("b".to_string(), Value::Int(2)),
+            ("a".to_string(), Value::string("x")),
+        ]))));
+        let value = list(vec![Value::Nil, Value::Bool(true), Value::Float(2.0), Value::string("hi"), map]);
+        assert_eq!(value.to_string(), "[nil, true, 2.0, \"hi\", {\"a\": \"x\", \"b\": 2}]");
+        assert_eq!(Value::string("hi").to_string(), "hi");
+    }
+
+    #[test]
+    fn a_list_containing_itself_displays_and_compares() {
+        let value = list(vec![Value::Int(1)]);
+        let Value::List(items) = &value else { unreachable!() };
+        items.borrow_mut().push(value.clone());
+        assert_eq!(value.to_string(), "[1, [...]]");
+        assert!(value == value.clone());
+        // Break the cycle so the test doesn't leak it
+        items.borrow_mut().clear();
+    }
+
+    #[test]
+    fn equality_promotes_numbers_and_compares_structure() {
+        assert_eq!(Value::Int(2), Value::Float(2.0));
+        assert_ne!(Value::Int(1), Value::Bool(true));
+        assert_ne!(Value::Nil, Value::Bool(false));
+        assert_eq!(list(vec![Value::Int(1), Value::string("a")]), list(vec![Value::Float(1.0), Value::string("a")]));
+        assert_ne!(list(vec![Value::Int(1)]), list(vec![Value::Int(1), Value::Int(2)]));
+    }
+
+    #[test]
+    fn truthiness_and_type_names() {
+        let falsy = [Value::Nil, Value::Bool(false), Value::Int(0), Value::Float(0.0), Value::string(""), list(vec![])];
+        assert!(falsy.iter().all(|value| !value.is_truthy()));
+        assert!(Value::Int(-1).is_truthy() && Value::string("0").is_truthy());
+        let names: Vec<&str> = falsy.iter().map(Value::type_name).collect();
+        assert_eq!(names, ["nil", "bool", "int", "float", "string", "list"]);
+    }
+}
@@ -0,0 +1,508 @@
+// src/verifier.rs - FalconCore bytecode verifier (operand bounds, stack depth, call arity)
+//
+// Runs once before execution so the VM can index constants, slots and upvalues without checks.
+// The code is interpreted abstractly from the script entry and from every function's start:
+// each instruction is reached with one stack depth on every path, and values are only tracked
+// far enough to know when a call's callee is a function of known arity.
+use crate::compiler::{Opcode, Program};
+use crate::error::{ErrorKind, RuntimeError};
+use crate::natives;
+use crate::value::{Function, Value};
+use std::collections::HashMap;
+
+/// What the verifier knows about one stack value.
+#[derive(Debug, Clone, PartialEq)]
+enum Slot {
+    Unknown,
+    Callable { name: String, arity: usize },
+}
+
+/// Where verification started: the top-level script or a function body.
+#[derive(Clone, Copy)]
+enum Unit<'a> {
+    Script,
+    Function(&'a Function),
+}
+
+/// The abstract machine state on entry to one instruction.
+#[derive(Clone)]
+struct State {
+    // Index into the list of units; an instruction belongs to exactly one
+    unit: usize,
+    stack: Vec<Slot>,
+    // Local slots that are allocated on every path here. Fixed for a function's frame; the
+    // script's grow as it stores to them
+    allocated: usize,
+}
+
+struct Verifier<'a> {
+    constants: &'a [Value],
+    code: &'a [Opcode],
+    globals: &'a [String],
+    lines: &'a [usize],
+    // Slots the script's block locals may use
+    script_slots: usize,
+    // Global slots that always hold the same function: untouched natives and functions defined once
+    callables: HashMap<usize, Slot>,
+    // One entry per instruction plus one for running off the end of the code
+    states: Vec<Option<State>>,
+    worklist: Vec<usize>,
+}
+
+/// Checks that the code only refers to constants, globals, slots, upvalues and jump targets that exist, that
+/// the stack never underflows and has the same depth wherever paths meet, that functions return
+/// exactly one value, and that calls to known functions pass the right number of arguments.
+pub fn verify(program: &Program) -> Result<(), RuntimeError> {
+    verify_from(program, 0)
+}
+
+/// Like `verify`, but the script starts at instruction `entry`: a REPL entry appended to code that
+/// already ran and was verified.
+pub fn verify_from(program: &Program, entry: usize) -> Result<(), RuntimeError> {
+    let (constants, code) = (&program.constants[..], &program.code[..]);
+    let mut verifier = Verifier {
+        constants,
+        code,
+        globals: &program.globals,
+        lines: &program.lines,
+        script_slots: program.local_count,
+        callables: callables(program),
+        states: vec![None; code.len() + 1],
+        worklist: vec![],
+    };
+
+    let mut units = vec![(entry, Unit::Script, 0)];
+    for value in constants {
+        if let Value::Function(function) = value {
+            if function.start_ip >= code.len() {
+                let message = format!("{}() starts at instruction {}, past the end of the code", function.name, function.start_ip);
+                return Err(verifier.error(code.len(), message));
+            }
+            units.push((function.start_ip, Unit::Function(function), function.local_count));
+        }
+    }
+
+    for (id, (entry, unit, allocated)) in units.into_iter().enumerate() {
+        let state = State {
+            unit: id,
+            stack: vec![],
+            allocated,
+        };
+        verifier.flow(entry, entry, state)?;
+        while let Some(ip) = verifier.worklist.pop() {
+            verifier.step(ip, unit)?;
+        }
+    }
+    Ok(())
+}
+
+/// Globals whose value at any call is known: natives the script never assigns, and functions
+/// stored by exactly one `MakeFunction; StoreGlobal` pair.
+fn callables(program: &Program) -> HashMap<usize, Slot> {
+    let code = &program.code;
+    let mut stores: HashMap<usize, usize> = HashMap::new();
+    for op in code {
+        if let Opcode::StoreGlobal(slot) = op {
+            *stores.entry(*slot).or_default() += 1;
+        }
+    }
+
+    let mut callables = HashMap::new();
+    let natives: HashMap<&str, Value> = natives::globals().into_iter().collect();
+    for (slot, name) in program.globals.iter().enumerate() {
+        if let Some(Value::NativeFunction(native)) = natives.get(name.as_str()) {
+            if !stores.contains_key(&slot) {
+                let callable = Slot::Callable {
+                    name: native.name.to_string(),
+                    arity: native.arity,
+                };
+                callables.insert(slot, callable);
+            }
+        }
+    }
+    for (ip, op) in code.iter().enumerate().skip(1) {
+        let (Opcode::StoreGlobal(slot), Opcode::MakeFunction(idx)) = (op, &code[ip - 1]) else {
+            continue;
+        };
+        if let (Some(1), Some(Value::Function(function))) = (stores.get(slot), program.constants.get(*idx)) {
+            let callable = Slot::Callable {
+                name: function.name.clone(),
+                arity: function.arity,
+            };
+            callables.insert(*slot, callable);
+        }
+    }
+    callables
+}
+
+impl<'a> Verifier<'a> {
+    fn error(&self, ip: usize, message: String) -> RuntimeError {
+        RuntimeError {
+            line: self.lines.get(ip).copied().unwrap_or(0),
+            ..RuntimeError::new(ErrorKind::Verify, format!("instruction {}: {}", ip, message))
+        }
+    }
+
+    /// Records that the instruction at `from` continues at `target` with `state`, merging it
+    /// with what other paths already brought there.
+    fn flow(&mut self, from: usize, target: usize, state: State) -> Result<(), RuntimeError> {
+        if target > self.code.len() {
+            let message = format!("jump to {}, outside the code ({} instructions)", target, self.code.len());
+            return Err(self.error(from, message));
+        }
+        let Some(existing) = &mut self.states[target] else {
+            self.states[target] = Some(state);
+            self.worklist.push(target);
+            return Ok(());
+        };
+        if existing.unit != state.unit {
+            return Err(self.error(from, format!("jump to {}, inside another function", target)));
+        }
+        if existing.stack.len() != state.stack.len() {
+            let message = format!(
+                "stack depth is {} on one path and {} on another",
+                existing.stack.len(),
+                state.stack.len()
+            );
+            return Err(self.error(target, message));
+        }
+        // Only forget things, so this settles after a few rounds
+        let mut changed = false;
+        for (known, incoming) in existing.stack.iter_mut().zip(&state.stack) {
+            if known != incoming && *known != Slot::Unknown {
+                *known = Slot::Unknown;
+                changed = true;
+            }
+        }
+        if state.allocated < existing.allocated {
+            existing.allocated = state.allocated;
+            changed = true;
+        }
+        if changed {
+            self.worklist.push(target);
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, ip: usize, unit: Unit) -> Result<(), RuntimeError> {
+        let mut state = self.states[ip].clone().expect("only reached instructions are queued");
+        let Some(op) = self.code.get(ip) else {
+            // Running off the end stops the program, which only the script may do
+            return match unit {
+                Unit::Script if state.stack.len() <= 1 => Ok(()),
+                Unit::Script => Err(self.error(ip, format!("script ends with {} values on the stack", state.stack.len()))),
+                Unit::Function(function) => Err(self.error(ip, format!("{}() runs past the end of the code", function.name))),
+            };
+        };
+
+        match op {
+            Opcode::LoadConst(idx) => {
+                self.constant(ip, *idx)?;
+                state.stack.push(Slot::Unknown);
+            }
+            Opcode::LoadGlobal(slot) => {
+                self.global(ip, *slot)?;
+                state.stack.push(self.callables.get(slot).cloned().unwrap_or(Slot::Unknown));
+            }
+            Opcode::StoreGlobal(slot) => {
+                self.global(ip, *slot)?;
+                self.pop(ip, &mut state, 1)?;
+            }
+            Opcode::Pop | Opcode::Print => {
+                self.pop(ip, &mut state, 1)?;
+            }
+            Opcode::LoadLocal(slot) => {
+                self.slot(ip, unit, &state, *slot)?;
+                state.stack.push(Slot::Unknown);
+            }
+            Opcode::StoreLocal(slot) => {
+                self.pop(ip, &mut state, 1)?;
+                self.store_slot(ip, unit, &mut state, *slot)?;
+            }
+            Opcode::LoadUpvalue(index) => {
+                self.upvalue(ip, unit, *index)?;
+                state.stack.push(Slot::Unknown);
+            }
+            Opcode::StoreUpvalue(index) => {
+                self.upvalue(ip, unit, *index)?;
+                self.pop(ip, &mut state, 1)?;
+            }
+            Opcode::CloseUpvalues(_) | Opcode::PopHandler => {}
+            Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::Div
+            | Opcode::Mod
+            | Opcode::Equal
+            | Opcode::NotEqual
+            | Opcode::Greater
+            | Opcode::Less
+            | Opcode::GreaterEqual
+            | Opcode::LessEqual
+            | Opcode::GetIndex => {
+                self.pop(ip, &mut state, 2)?;
+                state.stack.push(Slot::Unknown);
+            }
+            Opcode::Negate | Opcode::Not | Opcode::Stringify | Opcode::MakeSecret | Opcode::GetMember(_) => {
+                self.pop(ip, &mut state, 1)?;
+                state.stack.push(Slot::Unknown);
+            }
+            Opcode::Invoke(_, arg_count) => {
+                self.pop(ip, &mut state, arg_count.saturating_add(1))?;
+                state.stack.push(Slot::Unknown);
+            }
+            Opcode::BuildList(count) => {
+                self.pop(ip, &mut state, *count)?;
+                state.stack.push(Slot::Unknown);
+            }
+            Opcode::BuildMap(count) => {
+                self.pop(ip, &mut state, count.saturating_mul(2))?;
+                state.stack.push(Slot::Unknown);
+            }
+            Opcode::SetIndex => {
+                self.pop(ip, &mut state, 3)?;
+            }
+            Opcode::Dup => {
+                let top = self.pop(ip, &mut state, 1)?;
+                state.stack.extend(top.iter().cloned());
+                state.stack.extend(top);
+            }
+            Opcode::Dup2 => {
+                let top_two = self.pop(ip, &mut state, 2)?;
+                state.stack.extend(top_two.iter().cloned());
+                state.stack.extend(top_two);
+            }
+            Opcode::JumpIfFalse(target) => {
+                self.pop(ip, &mut state, 1)?;
+                self.flow(ip, *target, state.clone())?;
+            }
+            Opcode::Jump(target) => return self.flow(ip, *target, state),
+            Opcode::ForRange(slot, exit) | Opcode::ForIter(slot, exit) => {
+                // Two hidden slots, both set up before the loop
+                self.slot(ip, unit, &state, slot.saturating_add(1))?;
+                self.flow(ip, *exit, state.clone())?;
+                state.stack.push(Slot::Unknown);
+            }
+            Opcode::PushHandler(catch_ip, _) => {
+                // The handler starts with the stack as it is now, plus the error value
+                let mut at_catch = state.clone();
+                at_catch.stack.push(Slot::Unknown);
+                self.flow(ip, *catch_ip, at_catch)?;
+            }
+            Opcode::Throw => {
+                self.pop(ip, &mut state, 1)?;
+                return Ok(());
+            }
+            Opcode::MakeFunction(idx) => {
+                let Value::Function(function) = self.constant(ip, *idx)? else {
+                    return Err(self.error(ip, format!("constant {} is not a function", idx)));
+                };
+                for capture in &function.captures {
+                    if capture.is_local {
+                        // A recursive fn in a script block captures its own slot before storing to it
+                        self.store_slot(ip, unit, &mut state, capture.index)?;
+                    } else {
+                        self.upvalue(ip, unit, capture.index)?;
+                    }
+                }
+                state.stack.push(Slot::Callable {
+                    name: function.name.clone(),
+                    arity: function.arity,
+                });
+            }
+            Opcode::Call(arg_count) => {
+                let popped = self.pop(ip, &mut state, arg_count.saturating_add(1))?;
+                if let Slot::Callable { name, arity } = &popped[0] {
+                    if arity != arg_count {
+                        let message = format!("{}() expects {} argument(s), got {}", name, arity, arg_count);
+                        return Err(self.error(ip, message));
+                    }
+                }
+                state.stack.push(Slot::Unknown);
+            }
+            Opcode::Return => {
+                // The return value is left for the caller; the script's is the program's result
+                return match unit {
+                    Unit::Script if state.stack.len() <= 1 => Ok(()),
+                    Unit::Function(_) if state.stack.len() == 1 => Ok(()),
+                    _ => Err(self.error(ip, format!("return with {} values on the stack", state.stack.len()))),
+                };
+            }
+        }
+        self.flow(ip, ip + 1, state)
+    }
+
+    fn constant(&self, ip: usize, idx: usize) -> Result<&'a Value, RuntimeError> {
+        self.constants
+            .get(idx)
+            .ok_or_else(|| self.error(ip, format!("constant {} out of range ({} constants)", idx, self.constants.len())))
+    }
+
+    fn global(&self, ip: usize, slot: usize) -> Result<(), RuntimeError> {
+        if slot >= self.globals.len() {
+            return Err(self.error(ip, format!("global {} out of range ({} globals)", slot, self.globals.len())));
+        }
+        Ok(())
+    }
+
+    /// Pops `count` values, in the order they were pushed.
+    fn pop(&self, ip: usize, state: &mut State, count: usize) -> Result<Vec<Slot>, RuntimeError> {
+        if count > state.stack.len() {
+            let message = format!("stack underflow: needs {} value(s), has {}", count, state.stack.len());
+            return Err(self.error(ip, message));
+        }
+        Ok(state.stack.split_off(state.stack.len() - count))
+    }
+
+    /// A slot that is read: it must exist in the frame, or have been stored to at script level.
+    fn slot(&self, ip: usize, unit: Unit, state: &State, slot: usize) -> Result<(), RuntimeError> {
+        if slot >= state.allocated {
+            let message = match unit {
+                Unit::Script => format!("slot {} read before it is set", slot),
+                Unit::Function(function) => {
+                    format!("slot {} out of range for {}() ({} slots)", slot, function.name, function.local_count)
+                }
+            };
+            return Err(self.error(ip, message));
+        }
+        Ok(())
+    }
+
+    /// A slot that is written. The script's slots count as set from here on, up to the number it
+    /// declares; a function's are all there from the start.
+    fn store_slot(&self, ip: usize, unit: Unit, state: &mut State, slot: usize) -> Result<(), RuntimeError> {
+        match unit {
+            Unit::Script if slot >= self.script_slots => {
+                let message = format!("slot {} out of range for the script ({} slots)", slot, self.script_slots);
+                return Err(self.error(ip, message));
+            }
+            Unit::Script => state.allocated = state.allocated.max(slot + 1),
+            Unit::Function(_) => self.slot(ip, unit, state, slot)?,
+        }
+        Ok(())
+    }
+
+    fn upvalue(&self, ip: usize, unit: Unit, index: usize) -> Result<(), RuntimeError> {
+        match unit {
+            Unit::Script => Err(self.error(ip, "upvalue used outside a function".to_string())),
+            Unit::Function(function) if index >= function.captures.len() => {
+                let message =
+                    format!("upvalue {} out of range for {}() ({} captured)", index, function.name, function.captures.len());
+                Err(self.error(ip, message))
+            }
+            Unit::Function(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn compile(source: &str) -> Program {
+        let ast = Parser::new(Lexer::new(source)).parse().expect("test scripts parse");
+        let mut compiler = Compiler::new();
+        compiler.compile(ast);
+        compiler.program()
+    }
+
+    /// A script of hand-written code, with one int constant and no globals.
+    fn script(code: Vec<Opcode>, local_count: usize) -> Program {
+        Program {
+            constants: vec![Value::Int(1)],
+            lines: vec![1; code.len()],
+            code,
+            globals: vec![],
+            local_count,
+        }
+    }
+
+    fn rejection(program: &Program) -> String {
+        verify(program).expect_err("malformed code is rejected").message
+    }
+
+    #[test]
+    fn accepts_compiled_scripts() {
+        let source = "fn counter() {
+    let n = 0
+    fn next() {
+        n += 1
+        return n
+    }
+    return next
+}
+let next = counter()
+let total = 0
+for i in 0..3 {
+    let step = next()
+    try {
+        total += step and i
+    } catch e {
+        print e
+    }
+}
+return total";
+        assert!(verify(&compile(source)).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_operands() {
+        let jump = script(vec![Opcode::Jump(10)], 0);
+        assert!(rejection(&jump).contains("outside the code"));
+        let constant = script(vec![Opcode::LoadConst(3), Opcode::Return], 0);
+        assert!(rejection(&constant).contains("constant 3 out of range"));
+        let global = script(vec![Opcode::LoadGlobal(0), Opcode::Return], 0);
+        assert!(rejection(&global).contains("global 0 out of range"));
+        let upvalue = script(vec![Opcode::LoadUpvalue(0), Opcode::Return], 0);
+        assert!(rejection(&upvalue).contains("upvalue used outside a function"));
+    }
+
+    #[test]
+    fn rejects_stack_misuse() {
+        let underflow = script(vec![Opcode::LoadConst(0), Opcode::Add, Opcode::Return], 0);
+        assert!(rejection(&underflow).contains("stack underflow"));
+        let leftover = script(vec![Opcode::LoadConst(0), Opcode::LoadConst(0), Opcode::Return], 0);
+        assert!(rejection(&leftover).contains("return with 2 values"));
+        // One path reaches the return with a value pushed, the other without
+        let uneven = script(
+            vec![
+                Opcode::LoadConst(0),
+                Opcode::JumpIfFalse(3),
+                Opcode::LoadConst(0),
+                Opcode::Return,
+            ],
+            0,
+        );
+        assert!(rejection(&uneven).contains("stack depth is"));
+    }
+
+    #[test]
+    fn rejects_script_slots_out_of_bounds() {
+        let unset = script(vec![Opcode::LoadLocal(0), Opcode::Return], 1);
+        assert!(rejection(&unset).contains("slot 0 read before it is set"));
+        let past_count = script(vec![Opcode::LoadConst(0), Opcode::StoreLocal(5), Opcode::Return], 1);
+        assert!(rejection(&past_count).contains("slot 5 out of range for the script (1 slots)"));
+        let huge = script(vec![Opcode::LoadConst(0), Opcode::StoreLocal(0x7fff_fff0), Opcode::Return], 1);
+        assert!(verify(&huge).is_err());
+    }
+
+    #[test]
+    fn rejects_function_slots_out_of_bounds() {
+        let mut program = compile("fn f(a) { return a }\nreturn f(1)");
+        for op in &mut program.code {
+            if let Opcode::LoadLocal(slot) = op {
+                *slot = 7;
+            }
+        }
+        assert!(rejection(&program).contains("slot 7 out of range for f()"));
+    }
+
+    #[test]
+    fn rejects_calls_with_the_wrong_arity() {
+        assert!(rejection(&compile("return char(65, 66)")).contains("char() expects 1 argument(s), got 2"));
+        assert!(rejection(&compile("fn f(a) { return a }\nreturn f()")).contains("f() expects 1 argument(s), got 0"));
+    }
+}
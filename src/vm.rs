@@ -1,173 +1,1180 @@
-// src/vm.rs - FalconCore VM (Complete with And, Or, Not + logical ops)
-use crate::compiler::Opcode;
-use crate::parser::Expr;
+// src/vm.rs - FalconCore VM (stack machine over runtime Values)
+use crate::bytecode::{self, FormatError};
+use crate::chunk::{self, op, Chunk, WIDTH};
+use crate::compiler::{Opcode, Program};
+use std::cmp::Ordering;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
+
+pub use crate::value::Value;
+use crate::error::{ErrorKind, RuntimeError, TraceFrame};
+use crate::gc::{self, Heap, HeapConfig};
+use crate::methods;
+use crate::natives;
+use crate::verifier;
+use crate::value::{Closure, Upvalue};
+
+/// Deepest nesting of Falcon calls before a call fails with a StackOverflow error.
+const MAX_FRAMES: usize = 1000;
+
+/// An active call: where to resume and where its slots start in `locals`.
+struct CallFrame {
+    closure: Rc<Closure>,
+    return_ip: usize,
+    base: usize,
+}
+
+/// An installed `try`: where its handler starts and what to keep when an error unwinds to it.
+struct Handler {
+    catch_ip: usize,
+    frame_depth: usize,
+    stack_len: usize,
+    // First slot of the protected block, relative to its frame
+    slot: usize,
+}
 
 pub struct VM {
-    stack: Vec<Expr>,
-    constants: Vec<Expr>,
-    code: Vec<Opcode>,
+    stack: Vec<Value>,
+    // Held until the first `run` verifies it and assembles `chunk`
+    program: Option<Program>,
+    // Instruction the script starts at; past 0 only for REPL entries appended to earlier code
+    entry: usize,
+    chunk: Chunk,
+    // Byte offset of the current instruction in `chunk.code`
     ip: usize,
-    variables: HashMap<String, Expr>,
-    functions: HashMap<String, (Vec<String>, usize)>, // name → (params, start_ip)
-    call_stack: Vec<(usize, HashMap<String, Expr>)>,
-    loop_stack: Vec<(usize, i64)>,
+    // Indexed by global slot; None until assigned
+    globals: Vec<Option<Value>>,
+    global_names: Vec<String>,
+    // Slots of every active frame, innermost last
+    locals: Vec<Value>,
+    frames: Vec<CallFrame>,
+    // Upvalues still pointing into `locals`, so closures created over the same slot share one
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    // Innermost `try` last
+    handlers: Vec<Handler>,
+    // Installed for the thread while `run` executes
+    heap: Heap,
 }
 
 impl VM {
-    pub fn new(constants: Vec<Expr>, code: Vec<Opcode>) -> Self {
+    pub fn new(program: Program) -> Self {
+        // Builtins are ordinary globals holding native function values
+        let mut builtins: HashMap<&str, Value> = natives::globals().into_iter().collect();
+        let globals = program.globals.iter().map(|name| builtins.remove(name.as_str())).collect();
         VM {
             stack: vec![],
-            constants,
-            code,
+            global_names: program.globals.clone(),
+            program: Some(program),
+            entry: 0,
+            chunk: Chunk::default(),
             ip: 0,
-            variables: HashMap::new(),
-            functions: HashMap::new(),
-            call_stack: vec![],
-            loop_stack: vec![],
+            globals,
+            locals: vec![],
+            frames: vec![],
+            open_upvalues: vec![],
+            handlers: vec![],
+            heap: Heap::default(),
+        }
+    }
+
+    /// Sets the collector's thresholds and the heap limit.
+    pub fn with_heap_config(mut self, config: HeapConfig) -> Self {
+        self.heap = Heap::new(config);
+        self
+    }
+
+    /// A VM for a precompiled .fbc program; fails if the bytes aren't a valid bytecode file.
+    /// The code itself is verified when it runs, like any other program.
+    pub fn load(bytes: &[u8]) -> Result<Self, FormatError> {
+        Ok(VM::new(bytecode::decode(bytes)?))
+    }
+
+    /// Runs the program to completion. Returns the value of a top-level `return`, or nil.
+    pub fn run(&mut self) -> Result<Value, RuntimeError> {
+        // Nothing below bounds-checks operands, so malformed code is rejected up front
+        if let Some(program) = &self.program {
+            verifier::verify_from(program, self.entry)?;
+            // The script's block locals live at the bottom of `locals`, like a call frame's
+            if self.locals.len() < program.local_count {
+                self.locals.resize(program.local_count, Value::Nil);
+            }
+            self.chunk = Chunk::assemble(program);
+            self.ip = program.code[..self.entry].iter().map(|instruction| WIDTH[chunk::encode(instruction).0 as usize]).sum();
+            self.program = None;
+        }
+        gc::install(std::mem::take(&mut self.heap));
+        let result = loop {
+            match self.execute() {
+                Ok(value) => break Ok(value),
+                Err(error) => {
+                    if let Err(error) = self.unwind(error) {
+                        break Err(error);
+                    }
+                }
+            }
+        };
+        self.heap = gc::uninstall();
+        result
+    }
+
+    /// Runs `program` from instruction `entry`, keeping the globals and heap of earlier runs. The
+    /// program must extend the one that ran before, from the same compiler, so functions defined
+    /// earlier still find their code; the REPL runs each entry this way.
+    pub fn run_from(&mut self, program: Program, entry: usize) -> Result<Value, RuntimeError> {
+        // A failed run may leave frames and handlers behind; nothing in them is reachable now
+        self.close_upvalues(0);
+        self.stack.clear();
+        self.frames.clear();
+        self.handlers.clear();
+
+        let mut builtins: HashMap<&str, Value> = natives::globals().into_iter().collect();
+        for name in &program.globals[self.globals.len()..] {
+            self.globals.push(builtins.remove(name.as_str()));
         }
+        self.global_names = program.globals.clone();
+        self.program = Some(program);
+        self.entry = entry;
+        self.run()
+    }
+
+    /// Collects garbage, rooted in everything the VM can still reach. Only called between
+    /// instructions, when no value is held anywhere else.
+    fn collect_garbage(&mut self) -> Result<(), RuntimeError> {
+        gc::collect(|marker| {
+            let globals = self.globals.iter().flatten();
+            for value in self.stack.iter().chain(&self.locals).chain(globals).chain(&self.chunk.constants) {
+                marker.value(value);
+            }
+            for frame in &self.frames {
+                marker.closure(&frame.closure);
+            }
+            for upvalue in &self.open_upvalues {
+                marker.upvalue(upvalue);
+            }
+        })
+        .map_err(|e| self.locate(e))
     }
 
-    pub fn run(&mut self) {
-        while self.ip < self.code.len() {
-            let op = self.code[self.ip].clone();
-            match op {
-                Opcode::LoadConst(idx) => self.stack.push(self.constants[idx].clone()),
-                Opcode::LoadVar(name) => {
-                    let value = self.variables.get(&name).cloned().unwrap_or(Expr::String("undefined".to_string()));
+    /// Transfers control to the innermost handler with the error value on the stack, or gives the
+    /// error back if nothing catches it.
+    fn unwind(&mut self, error: RuntimeError) -> Result<(), RuntimeError> {
+        let Some(handler) = self.handlers.pop() else {
+            return Err(error);
+        };
+        let mut locals_len = None;
+        while self.frames.len() > handler.frame_depth {
+            let frame = self.frames.pop().expect("loop condition checked the length");
+            locals_len = Some(frame.base);
+        }
+        self.close_upvalues(self.frame_base() + handler.slot);
+        if let Some(len) = locals_len {
+            self.locals.truncate(len);
+        }
+        self.stack.truncate(handler.stack_len);
+        self.stack.push(error.to_value());
+        self.ip = handler.catch_ip;
+        Ok(())
+    }
+
+    fn execute(&mut self) -> Result<Value, RuntimeError> {
+        while self.ip < self.chunk.code.len() {
+            let instruction = self.chunk.code[self.ip];
+            match instruction {
+                op::LOAD_CONST => self.stack.push(self.chunk.constants[self.operand(0)].clone()),
+                op::LOAD_GLOBAL => {
+                    let slot = self.operand(0);
+                    let Some(value) = self.globals[slot].clone() else {
+                        let message = format!("Undefined variable '{}'", self.global_names[slot]);
+                        return Err(self.fail(ErrorKind::Undefined, message));
+                    };
                     self.stack.push(value);
                 }
-                Opcode::StoreVar(name) => {
-                    let value = self.stack.pop().unwrap();
-                    self.variables.insert(name, value);
+                op::STORE_GLOBAL => {
+                    let value = self.pop()?;
+                    let slot = self.operand(0);
+                    self.globals[slot] = Some(value);
                 }
-
-                Opcode::Add => {
-                    let right = self.stack.pop().unwrap();
-                    let left = self.stack.pop().unwrap();
-                    if let (Expr::Number(a), Expr::Number(b)) = (left, right) {
-                        self.stack.push(Expr::Number(a + b));
-                    }
+                op::LOAD_LOCAL => {
+                    let value = self.locals[self.frame_base() + self.operand(0)].clone();
+                    self.stack.push(value);
                 }
-                Opcode::Sub => {
-                    let right = self.stack.pop().unwrap();
-                    let left = self.stack.pop().unwrap();
-                    if let (Expr::Number(a), Expr::Number(b)) = (left, right) {
-                        self.stack.push(Expr::Number(a - b));
-                    }
+                op::STORE_LOCAL => {
+                    let value = self.pop()?;
+                    let idx = self.frame_base() + self.operand(0);
+                    self.locals[idx] = value;
                 }
-                Opcode::Mul => {
-                    let right = self.stack.pop().unwrap();
-                    let left = self.stack.pop().unwrap();
-                    if let (Expr::Number(a), Expr::Number(b)) = (left, right) {
-                        self.stack.push(Expr::Number(a * b));
-                    }
+                op::LOAD_UPVALUE => {
+                    let upvalue = self.upvalue(self.operand(0));
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(idx) => self.locals[*idx].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
                 }
-                Opcode::Div => {
-                    let right = self.stack.pop().unwrap();
-                    let left = self.stack.pop().unwrap();
-                    if let (Expr::Number(a), Expr::Number(b)) = (left, right) {
-                        if b == 0 { panic!("Division by zero"); }
-                        self.stack.push(Expr::Number(a / b));
+                op::STORE_UPVALUE => {
+                    let value = self.pop()?;
+                    let upvalue = self.upvalue(self.operand(0));
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(idx) => self.locals[*idx] = value,
+                        Upvalue::Closed(closed) => *closed = value,
                     }
                 }
+                op::CLOSE_UPVALUES => {
+                    let from = self.frame_base() + self.operand(0);
+                    self.close_upvalues(from);
+                }
+                op::POP => {
+                    self.pop()?;
+                }
 
-                // Logical opcodes (1 = true, 0 = false)
-                Opcode::And => {
-                    let right = self.stack.pop().unwrap();
-                    let left = self.stack.pop().unwrap();
-                    let result = if let (Expr::Number(a), Expr::Number(b)) = (left, right) {
-                        if a != 0 && b != 0 { 1 } else { 0 }
-                    } else { 0 };
-                    self.stack.push(Expr::Number(result));
-                }
-                Opcode::Or => {
-                    let right = self.stack.pop().unwrap();
-                    let left = self.stack.pop().unwrap();
-                    let result = if let (Expr::Number(a), Expr::Number(b)) = (left, right) {
-                        if a != 0 || b != 0 { 1 } else { 0 }
-                    } else { 0 };
-                    self.stack.push(Expr::Number(result));
-                }
-                Opcode::Not => {
-                    let value = self.stack.pop().unwrap();
-                    let result = if let Expr::Number(n) = value {
-                        if n == 0 { 1 } else { 0 }
-                    } else { 0 };
-                    self.stack.push(Expr::Number(result));
-                }
-
-                Opcode::Print => {
-                    let value = self.stack.pop().unwrap();
-                    match value {
-                        Expr::Number(n) => println!("{}", n),
-                        Expr::String(s) => println!("{}", s),
-                        _ => println!("{:?}", value),
-                    }
+                // Arithmetic: Int op Int stays Int, anything involving a Float is promoted to Float.
+                // A secret operand makes the result secret too.
+                op::ADD => self.binary(add)?,
+                op::SUB => self.binary(|l, r| arithmetic("-", l, r, i64::checked_sub, |a, b| a - b))?,
+                op::MUL => self.binary(|l, r| arithmetic("*", l, r, i64::checked_mul, |a, b| a * b))?,
+                op::DIV => {
+                    self.check_divisor()?;
+                    self.binary(|l, r| arithmetic("/", l, r, i64::checked_div, |a, b| a / b))?;
                 }
+                op::MOD => {
+                    self.check_divisor()?;
+                    self.binary(|l, r| arithmetic("%", l, r, i64::checked_rem, |a, b| a % b))?;
+                }
+                op::NEGATE => {
+                    let value = self.pop()?;
+                    let result = match value.expose() {
+                        Value::Int(n) => match n.checked_neg() {
+                            Some(n) => Value::Int(n),
+                            // Formats the operand as pushed, so a secret shows as [REDACTED]
+                            None => return Err(self.fail(ErrorKind::Overflow, format!("Integer overflow in -{}", value))),
+                        },
+                        Value::Float(f) => Value::Float(-f),
+                        other => return Err(self.fail(ErrorKind::Type, format!("Cannot negate a {}", other.type_name()))),
+                    };
+                    self.stack.push(if value.is_secret() { Value::secret(result) } else { result });
+                }
+
+                // Comparison opcodes; these look through secrets and yield plain booleans so scripts can branch
+                op::EQUAL => {
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+                    self.stack.push(Value::Bool(left.expose() == right.expose()));
+                }
+                op::NOT_EQUAL => {
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+                    self.stack.push(Value::Bool(left.expose() != right.expose()));
+                }
+                op::GREATER => self.compare(|o| o == Ordering::Greater)?,
+                op::LESS => self.compare(|o| o == Ordering::Less)?,
+                op::GREATER_EQUAL => self.compare(|o| o != Ordering::Less)?,
+                op::LESS_EQUAL => self.compare(|o| o != Ordering::Greater)?,
 
-                Opcode::JumpIfFalse(target) => {
-                    let cond = self.stack.pop().unwrap();
-                    if let Expr::Number(n) = cond {
-                        if n == 0 {
-                            self.ip = target;
+                // Logical opcodes; `and`/`or` compile to jumps
+                op::NOT => {
+                    let value = self.pop()?;
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                }
+
+                // Anything read out of a secret is secret too
+                op::GET_MEMBER => {
+                    let object = self.pop()?;
+                    let name = &self.chunk.names[self.operand(0)];
+                    let result = methods::get_property(object.expose(), name).map_err(|e| self.locate(e))?;
+                    self.stack.push(if object.is_secret() { Value::secret(result) } else { result });
+                }
+                op::INVOKE => {
+                    if gc::pending() {
+                        self.collect_garbage()?;
+                    }
+                    let args = self.pop_n(self.operand(1))?;
+                    let object = self.pop()?;
+                    let name = &self.chunk.names[self.operand(0)];
+                    // A function stored in a map (e.g. the `string` module) is called like a method
+                    let entry = match object.expose() {
+                        Value::Map(entries) => entries.borrow().get(name).cloned(),
+                        _ => None,
+                    };
+                    if let Some(callee @ (Value::Closure(_) | Value::NativeFunction(_))) = entry {
+                        if self.call(callee, args)? {
                             continue;
                         }
+                    } else {
+                        let result = methods::invoke(&object, name, &args).map_err(|e| self.locate(e))?;
+                        let tainted = object.is_secret() || args.iter().any(Value::is_secret);
+                        self.stack.push(if tainted { Value::secret(result) } else { result });
+                    }
+                }
+                op::STRINGIFY => {
+                    let value = self.pop()?;
+                    let text = value.expose().to_string();
+                    self.stack.push(if value.is_secret() { Value::secret_string(text) } else { Value::string(&text) });
+                }
+                op::BUILD_LIST => {
+                    let items = self.pop_n(self.operand(0))?;
+                    self.stack.push(methods::list(items));
+                }
+                op::BUILD_MAP => {
+                    let flat = self.pop_n(self.operand(0) * 2)?;
+                    let mut entries = HashMap::new();
+                    for pair in flat.chunks(2) {
+                        let key = methods::stored_key(&pair[0]).map_err(|e| self.locate(e))?;
+                        entries.insert(key.to_string(), pair[1].clone());
                     }
+                    self.stack.push(methods::map(entries));
+                }
+                op::GET_INDEX => {
+                    let index = self.pop()?;
+                    let object = self.pop()?;
+                    let result = self.get_index(&object, &index)?;
+                    let tainted = object.is_secret() || index.is_secret();
+                    self.stack.push(if tainted { Value::secret(result) } else { result });
+                }
+                op::SET_INDEX => {
+                    let value = self.pop()?;
+                    let index = self.pop()?;
+                    let object = self.pop()?;
+                    self.set_index(&object, &index, value)?;
                 }
-                Opcode::Jump(target) => {
-                    self.ip = target;
+                op::DUP => {
+                    let value = self.pop()?;
+                    self.stack.push(value.clone());
+                    self.stack.push(value);
+                }
+                op::DUP2 => {
+                    let top_two = self.pop_n(2)?;
+                    self.stack.extend(top_two.iter().cloned());
+                    self.stack.extend(top_two);
+                }
+
+                op::MAKE_SECRET => {
+                    let value = self.pop()?;
+                    self.stack.push(Value::secret(value));
+                }
+
+                op::PRINT => {
+                    let value = self.pop()?;
+                    println!("{}", value);
+                }
+
+                op::JUMP_IF_FALSE => {
+                    let cond = self.pop()?;
+                    if !cond.is_truthy() {
+                        self.ip = self.operand(0);
+                        continue;
+                    }
+                }
+                op::JUMP => {
+                    // Jumps and calls are the safe points: anything allocating without bound passes one
+                    if gc::pending() {
+                        self.collect_garbage()?;
+                    }
+                    self.ip = self.operand(0);
                     continue;
                 }
 
-                Opcode::RepeatStart => {
-                    let times = if let Expr::Number(n) = self.stack.pop().unwrap() {
-                        n
-                    } else {
-                        panic!("Repeat expects number");
-                    };
-                    self.loop_stack.push((self.ip, times));
-                }
-                Opcode::RepeatEnd => {
-                    if let Some((start_ip, mut count)) = self.loop_stack.pop() {
-                        count -= 1;
-                        if count > 0 {
-                            self.loop_stack.push((start_ip, count));
-                            self.ip = start_ip;
-                            continue;
+                op::FOR_RANGE => {
+                    let idx = self.frame_base() + self.operand(0);
+                    let (next, end) = match (self.locals[idx].expose(), self.locals[idx + 1].expose()) {
+                        (Value::Int(next), Value::Int(end)) => (*next, *end),
+                        (a, b) => {
+                            let message = format!("Loop bounds must be ints, got {} and {}", a.type_name(), b.type_name());
+                            return Err(self.fail(ErrorKind::Type, message));
                         }
+                    };
+                    if next >= end {
+                        self.ip = self.operand(1);
+                        continue;
                     }
+                    self.locals[idx] = Value::Int(next + 1);
+                    self.stack.push(Value::Int(next));
                 }
-
-                Opcode::Call(name, arg_count) => {
-                    if let Some((params, start_ip)) = self.functions.get(&name) {
-                        if *arg_count != params.len() {
-                            panic!("Argument count mismatch");
+                op::FOR_ITER => {
+                    let idx = self.frame_base() + self.operand(0);
+                    // The compiler always starts the position at 0, but a hand-made .fbc needn't
+                    let position = match &self.locals[idx + 1] {
+                        Value::Int(position) if *position >= 0 => *position,
+                        other => {
+                            let message = format!("Loop position must be a non-negative int, got {}", other.type_name());
+                            return Err(self.fail(ErrorKind::Type, message));
+                        }
+                    };
+                    let iterable = &self.locals[idx];
+                    let item = match iterable.expose() {
+                        Value::List(items) => items.borrow().get(position as usize).cloned(),
+                        Value::Str(s) => s.chars().nth(position as usize).map(|c| Value::string(&c.to_string())),
+                        Value::Map(entries) => {
+                            let entries = entries.borrow();
+                            let mut keys: Vec<&String> = entries.keys().collect();
+                            keys.sort();
+                            keys.get(position as usize).map(|key| Value::string(key))
                         }
-                        let mut locals = HashMap::new();
-                        for param in params.iter().rev() {
-                            let arg = self.stack.pop().unwrap();
-                            locals.insert(param.clone(), arg);
+                        other => {
+                            let message = format!("Cannot iterate over {}", other.type_name());
+                            return Err(self.fail(ErrorKind::Type, message));
                         }
-                        self.call_stack.push((self.ip + 1, self.variables.clone()));
-                        self.variables = locals;
-                        self.ip = *start_ip;
+                    };
+                    let Some(item) = item else {
+                        self.ip = self.operand(1);
                         continue;
-                    }
+                    };
+                    // Items of a secret collection stay secret
+                    let item = if iterable.is_secret() { Value::secret(item) } else { item };
+                    self.locals[idx + 1] = Value::Int(position + 1);
+                    self.stack.push(item);
+                }
+
+                op::PUSH_HANDLER => self.handlers.push(Handler {
+                    catch_ip: self.operand(0),
+                    frame_depth: self.frames.len(),
+                    stack_len: self.stack.len(),
+                    slot: self.operand(1),
+                }),
+                op::POP_HANDLER => {
+                    self.handlers.pop();
                 }
-                Opcode::Return => {
-                    if let Some((return_ip, saved_locals)) = self.call_stack.pop() {
-                        self.variables = saved_locals;
-                        self.ip = return_ip;
+                op::THROW => {
+                    let value = self.pop()?;
+                    return Err(self.locate(RuntimeError::thrown(value)));
+                }
+
+                op::MAKE_FUNCTION => {
+                    let Value::Function(function) = &self.chunk.constants[self.operand(0)] else {
+                        unreachable!("MakeFunction always refers to a function constant");
+                    };
+                    let function = function.clone();
+                    let base = self.frame_base();
+                    let upvalues = function
+                        .captures
+                        .iter()
+                        .map(|capture| {
+                            if capture.is_local {
+                                self.capture_upvalue(base + capture.index)
+                            } else {
+                                self.upvalue(capture.index)
+                            }
+                        })
+                        .collect();
+                    let closure = Rc::new(Closure { function, upvalues });
+                    gc::track_closure(&closure);
+                    self.stack.push(Value::Closure(closure));
+                }
+                op::CALL => {
+                    if gc::pending() {
+                        self.collect_garbage()?;
+                    }
+                    // Stack layout: callee, arg1 .. argN
+                    let args = self.pop_n(self.operand(0))?;
+                    let callee = self.pop()?;
+                    if self.call(callee, args)? {
                         continue;
-                    } else {
-                        break;
                     }
                 }
-                _ => println!("Unsupported opcode: {:?}", op),
+                op::RETURN => {
+                    let Some(frame) = self.frames.pop() else {
+                        return Ok(self.stack.pop().unwrap_or(Value::Nil));
+                    };
+                    self.close_upvalues(frame.base);
+                    self.locals.truncate(frame.base);
+                    self.ip = frame.return_ip;
+                    continue;
+                }
+                _ => unreachable!("assembled code only holds known opcodes"),
             }
-            self.ip += 1;
+            self.ip += WIDTH[instruction as usize];
         }
-        println!("VM finished!");
+        Ok(self.stack.pop().unwrap_or(Value::Nil))
     }
-                            }
+
+    /// Calls `callee`. Returns true if a new frame was entered and `ip` already points into it;
+    /// a native's result is pushed immediately instead.
+    fn call(&mut self, callee: Value, args: Vec<Value>) -> Result<bool, RuntimeError> {
+        match callee {
+            Value::Closure(closure) => {
+                let function = &closure.function;
+                self.check_arity(&function.name, function.arity, args.len())?;
+                if self.frames.len() >= MAX_FRAMES {
+                    let message = format!("Stack overflow: more than {} nested calls", MAX_FRAMES);
+                    return Err(self.fail(ErrorKind::StackOverflow, message));
+                }
+                let base = self.locals.len();
+                self.locals.extend(args);
+                self.locals.resize(base + function.local_count, Value::Nil);
+                let start_ip = function.start_ip;
+                self.frames.push(CallFrame {
+                    closure,
+                    return_ip: self.ip + WIDTH[self.chunk.code[self.ip] as usize],
+                    base,
+                });
+                self.ip = start_ip;
+                Ok(true)
+            }
+            Value::NativeFunction(native) => {
+                self.check_arity(native.name, native.arity, args.len())?;
+                let result = (native.func)(&args).map_err(|e| self.locate(e))?;
+                self.stack.push(result);
+                Ok(false)
+            }
+            other => Err(self.fail(ErrorKind::Type, format!("Can only call functions, got {}", other.type_name()))),
+        }
+    }
+
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(self.fail(ErrorKind::StackUnderflow, "Stack underflow")),
+        }
+    }
+
+    /// The top `count` values, in the order they were pushed.
+    fn pop_n(&mut self, count: usize) -> Result<Vec<Value>, RuntimeError> {
+        if count > self.stack.len() {
+            return Err(self.fail(ErrorKind::StackUnderflow, "Stack underflow"));
+        }
+        Ok(self.stack.split_off(self.stack.len() - count))
+    }
+
+    fn frame_base(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.base)
+    }
+
+    fn upvalue(&self, index: usize) -> Rc<RefCell<Upvalue>> {
+        let frame = self.frames.last().expect("upvalues are only used inside functions");
+        frame.closure.upvalues[index].clone()
+    }
+
+    fn capture_upvalue(&mut self, idx: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open == idx));
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(idx)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Moves the values of slots `from..` into the upvalues that captured them.
+    fn close_upvalues(&mut self, from: usize) {
+        let locals = &self.locals;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(idx) if idx >= from => {
+                    *upvalue = Upvalue::Closed(locals.get(idx).cloned().unwrap_or(Value::Nil));
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    /// Source line of the instruction at `ip`, or 0 without a line table.
+    fn line_at(&self, ip: usize) -> usize {
+        self.chunk.line_at(ip)
+    }
+
+    /// The `n`th operand of the current instruction.
+    fn operand(&self, n: usize) -> usize {
+        let at = self.ip + 1 + 4 * n;
+        u32::from_le_bytes(self.chunk.code[at..at + 4].try_into().expect("4 bytes")) as usize
+    }
+
+    /// The active Falcon calls, innermost first, each with the line it is currently on.
+    fn backtrace(&self) -> Vec<TraceFrame> {
+        let mut ip = self.ip;
+        let mut trace = vec![];
+        for frame in self.frames.iter().rev() {
+            trace.push(TraceFrame {
+                function: frame.closure.function.name.clone(),
+                line: self.line_at(ip),
+            });
+            // The caller is paused on its Call/Invoke instruction
+            ip = frame.return_ip - 1;
+        }
+        trace.push(TraceFrame {
+            function: "<script>".to_string(),
+            line: self.line_at(ip),
+        });
+        trace
+    }
+
+    /// A runtime error at the current instruction.
+    fn fail(&self, kind: ErrorKind, message: impl Into<String>) -> RuntimeError {
+        self.locate(RuntimeError::new(kind, message))
+    }
+
+    /// Attaches the current line and backtrace to an error raised by a native or method.
+    fn locate(&self, mut error: RuntimeError) -> RuntimeError {
+        if error.line == 0 {
+            error.line = self.line_at(self.ip);
+        }
+        error.backtrace = self.backtrace();
+        error
+    }
+
+    /// Takes the operands as pushed; errors format the index itself, so a secret one shows as [REDACTED].
+    fn get_index(&self, object: &Value, index: &Value) -> Result<Value, RuntimeError> {
+        match (object.expose(), index.expose()) {
+            (Value::List(items), Value::Int(i)) => {
+                let items = items.borrow();
+                let item = usize::try_from(*i).ok().and_then(|i| items.get(i));
+                item.cloned().ok_or_else(|| {
+                    self.fail(ErrorKind::Index, format!("Index {} out of range for list of length {}", index, items.len()))
+                })
+            }
+            (Value::Str(s), Value::Int(i)) => {
+                let c = usize::try_from(*i).ok().and_then(|i| s.chars().nth(i)).ok_or_else(|| {
+                    self.fail(ErrorKind::Index, format!("Index {} out of range for string of length {}", index, s.chars().count()))
+                })?;
+                // GET_INDEX keeps the result secret; build it there directly rather than as tracked text
+                if object.is_secret() || index.is_secret() {
+                    Ok(Value::secret_string(c.to_string()))
+                } else {
+                    Ok(Value::string(&c.to_string()))
+                }
+            }
+            // A missing key reads as nil
+            (Value::Map(entries), key) => {
+                let key = methods::key(key).map_err(|e| self.locate(e))?;
+                Ok(entries.borrow().get(key).cloned().unwrap_or(Value::Nil))
+            }
+            (object, index) => Err(self.fail(
+                ErrorKind::Type,
+                format!("Cannot index {} with {}", object.type_name(), index.type_name()),
+            )),
+        }
+    }
+
+    /// Like `get_index`, takes the operands as pushed.
+    fn set_index(&self, object: &Value, index: &Value, value: Value) -> Result<(), RuntimeError> {
+        match (object.expose(), index.expose()) {
+            (Value::List(items), Value::Int(i)) => {
+                let mut items = items.borrow_mut();
+                let len = items.len();
+                match usize::try_from(*i).ok().and_then(|i| items.get_mut(i)) {
+                    Some(slot) => *slot = value,
+                    None => {
+                        let message = format!("Index {} out of range for list of length {}", index, len);
+                        return Err(self.fail(ErrorKind::Index, message));
+                    }
+                }
+            }
+            (Value::Map(entries), _) => {
+                let key = methods::stored_key(index).map_err(|e| self.locate(e))?;
+                if entries.borrow_mut().insert(key.to_string(), value).is_none() {
+                    gc::grow(key.len() + std::mem::size_of::<(String, Value)>());
+                }
+            }
+            (object, index) => {
+                let message = format!("Cannot assign into {} with a {} index", object.type_name(), index.type_name());
+                return Err(self.fail(ErrorKind::Type, message));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_arity(&self, name: &str, arity: usize, arg_count: usize) -> Result<(), RuntimeError> {
+        if arg_count != arity {
+            let message = format!("{}() expects {} argument(s), got {}", name, arity, arg_count);
+            return Err(self.fail(ErrorKind::Arity, message));
+        }
+        Ok(())
+    }
+
+    /// Pops two operands, applies `op` to their plaintext and re-taints the result if either was secret.
+    fn binary(&mut self, op: impl Fn(&Value, &Value) -> Result<Value, RuntimeError>) -> Result<(), RuntimeError> {
+        let right = self.pop()?;
+        let left = self.pop()?;
+        // The operators look through secrets themselves, so their errors can name the operands safely
+        let result = op(&left, &right).map_err(|e| self.locate(e))?;
+        if left.is_secret() || right.is_secret() {
+            self.stack.push(Value::secret(result));
+        } else {
+            self.stack.push(result);
+        }
+        Ok(())
+    }
+
+    fn check_divisor(&self) -> Result<(), RuntimeError> {
+        match self.stack.last().map(Value::expose) {
+            Some(divisor) if is_zero(divisor) => Err(self.fail(ErrorKind::DivisionByZero, "Division by zero")),
+            _ => Ok(()),
+        }
+    }
+
+    fn compare(&mut self, test: impl Fn(Ordering) -> bool) -> Result<(), RuntimeError> {
+        let right = self.pop()?;
+        let left = self.pop()?;
+        let (left, right) = (left.expose(), right.expose());
+        let Some(ordering) = ordering(left, right) else {
+            let message = format!("Cannot compare {} with {}", left.type_name(), right.type_name());
+            return Err(self.fail(ErrorKind::Type, message));
+        };
+        // NaN compares false against everything
+        self.stack.push(Value::Bool(ordering.is_some_and(test)));
+        Ok(())
+    }
+}
+
+/// How two values order for `<` and friends: None if they can't be compared at all, Some(None)
+/// when a NaN is involved.
+fn ordering(left: &Value, right: &Value) -> Option<Option<Ordering>> {
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => Some(Some(a.cmp(b))),
+        (Value::Str(a), Value::Str(b)) => Some(Some(a.cmp(b))),
+        _ => match (left.as_float(), right.as_float()) {
+            (Some(a), Some(b)) => Some(a.partial_cmp(&b)),
+            _ => None,
+        },
+    }
+}
+
+/// What a pure opcode gives for constant operands, computed the way the VM would at run time.
+/// None where the VM would raise an error instead, or where the result couldn't be a constant.
+pub fn fold(instruction: &Opcode, operands: &[Value]) -> Option<Value> {
+    let compare = |left, right, test: fn(Ordering) -> bool| ordering(left, right).map(|o| o.is_some_and(test));
+    let result = match (instruction, operands) {
+        (Opcode::Negate, [Value::Int(n)]) => Value::Int(n.checked_neg()?),
+        (Opcode::Negate, [Value::Float(f)]) => Value::Float(-f),
+        (Opcode::Not, [value]) => Value::Bool(!value.is_truthy()),
+        (Opcode::Stringify, [value]) => Value::string(&value.to_string()),
+        (Opcode::Add, [l, r]) => add(l, r).ok()?,
+        (Opcode::Sub, [l, r]) => arithmetic("-", l, r, i64::checked_sub, |a, b| a - b).ok()?,
+        (Opcode::Mul, [l, r]) => arithmetic("*", l, r, i64::checked_mul, |a, b| a * b).ok()?,
+        (Opcode::Div | Opcode::Mod, [_, r]) if is_zero(r) => return None,
+        (Opcode::Div, [l, r]) => arithmetic("/", l, r, i64::checked_div, |a, b| a / b).ok()?,
+        (Opcode::Mod, [l, r]) => arithmetic("%", l, r, i64::checked_rem, |a, b| a % b).ok()?,
+        (Opcode::Equal, [l, r]) => Value::Bool(l == r),
+        (Opcode::NotEqual, [l, r]) => Value::Bool(l != r),
+        (Opcode::Greater, [l, r]) => Value::Bool(compare(l, r, |o| o == Ordering::Greater)?),
+        (Opcode::Less, [l, r]) => Value::Bool(compare(l, r, |o| o == Ordering::Less)?),
+        (Opcode::GreaterEqual, [l, r]) => Value::Bool(compare(l, r, |o| o != Ordering::Less)?),
+        (Opcode::LessEqual, [l, r]) => Value::Bool(compare(l, r, |o| o != Ordering::Greater)?),
+        _ => return None,
+    };
+    matches!(result, Value::Nil | Value::Bool(_) | Value::Int(_) | Value::Float(_) | Value::Str(_)).then_some(result)
+}
+
+fn is_zero(divisor: &Value) -> bool {
+    match divisor {
+        Value::Int(n) => *n == 0,
+        Value::Float(f) => *f == 0.0,
+        _ => false,
+    }
+}
+
+/// `+` coercion table:
+///
+/// | left           | right          | result                              |
+/// |----------------|----------------|-------------------------------------|
+/// | int/float      | int/float      | numeric sum, promoted like `-`      |
+/// | string         | string         | concatenation                       |
+/// | string         | int/float/bool/nil/list/map | string + display form  |
+/// | int/float/bool/nil/list/map | string | display form + string          |
+/// | list           | list           | new list with both sets of items    |
+///
+/// Anything else (functions, bool + int, map + map, ...) is a type error.
+/// Operands are as they were pushed, secrets included. Text joined from a secret goes straight into
+/// a secret instead of onto the tracked heap.
+fn add(left: &Value, right: &Value) -> Result<Value, RuntimeError> {
+    let secret = left.is_secret() || right.is_secret();
+    let text = |text: String| if secret { Value::secret_string(text) } else { Value::string(&text) };
+    match (left.expose(), right.expose()) {
+        (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+            arithmetic("+", left, right, i64::checked_add, |a, b| a + b)
+        }
+        (Value::Str(a), Value::Str(b)) => {
+            // Sized up front, so no partial copy is freed unwiped while it grows
+            let mut joined = String::with_capacity(a.len() + b.len());
+            joined.push_str(a);
+            joined.push_str(b);
+            Ok(text(joined))
+        }
+        (Value::Str(a), other) if is_stringable(other) => Ok(text(format!("{}{}", a, other))),
+        (other, Value::Str(b)) if is_stringable(other) => Ok(text(format!("{}{}", other, b))),
+        (Value::List(a), Value::List(b)) => {
+            let mut items = a.borrow().clone();
+            items.extend(b.borrow().iter().cloned());
+            Ok(methods::list(items))
+        }
+        (l, r) => Err(type_error("+", l, r)),
+    }
+}
+
+/// Values that `+` will turn into text next to a string.
+fn is_stringable(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::Int(_) | Value::Float(_) | Value::Bool(_) | Value::Nil | Value::List(_) | Value::Map(_)
+    )
+}
+
+/// Like `add`, takes the operands as pushed; an overflow message shows a secret as [REDACTED].
+fn arithmetic(
+    symbol: &str,
+    left: &Value,
+    right: &Value,
+    int_op: fn(i64, i64) -> Option<i64>,
+    float_op: fn(f64, f64) -> f64,
+) -> Result<Value, RuntimeError> {
+    match (left.expose(), right.expose()) {
+        (Value::Int(a), Value::Int(b)) => int_op(*a, *b)
+            .map(Value::Int)
+            .ok_or_else(|| RuntimeError::new(ErrorKind::Overflow, format!("Integer overflow in {} {} {}", left, symbol, right))),
+        (l, r) => match (l.as_float(), r.as_float()) {
+            (Some(a), Some(b)) => Ok(Value::Float(float_op(a, b))),
+            _ => Err(type_error(symbol, l, r)),
+        },
+    }
+}
+
+fn type_error(symbol: &str, left: &Value, right: &Value) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::Type,
+        format!("unsupported operand types for {}: {} and {}", symbol, left.type_name(), right.type_name()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn run(source: &str) -> Result<Value, RuntimeError> {
+        let ast = Parser::new(Lexer::new(source)).parse().expect("test scripts parse");
+        let mut compiler = Compiler::new();
+        compiler.compile(ast);
+        VM::new(compiler.program()).run()
+    }
+
+    /// What the script returns, as `print` would show it.
+    fn eval(source: &str) -> String {
+        run(source).expect("test scripts run").to_string()
+    }
+
+    fn error(source: &str) -> RuntimeError {
+        run(source).expect_err("the script fails")
+    }
+
+    #[test]
+    fn secret_map_keys_are_refused() {
+        let source = "secure let s = \"abc\"
+let m = {}
+let errors = []
+try { m[s] = 1 } catch e { errors.push(e.message) }
+try { m = { s: 2 } } catch e { errors.push(e.message) }
+m[\"x\"] = s
+return [m, errors]";
+        let result = run(source).unwrap();
+        assert_eq!(
+            result.to_string(),
+            "[{\"x\": [REDACTED]}, [\"Map keys can't be secret\", \"Map keys can't be secret\"]]"
+        );
+        assert!(!result.to_string().contains("abc"));
+    }
+
+    #[test]
+    fn methods_taint_results_from_secret_arguments() {
+        let source = "secure let k = 1
+secure let word = \"secret\"
+secure let m = { \"pin\": 1 }
+return [[1, 2, 3].slice(0, k), { \"a\": 1 }.contains(word), word.slice(0, 3), word[0], m.keys(), [1].length()]";
+        let Value::List(items) = run(source).unwrap() else { panic!("the script returns a list") };
+        let items = items.borrow();
+        for item in &items[..5] {
+            assert!(item.is_secret(), "{:?} isn't secret", item);
+        }
+        assert!(!items[5].is_secret());
+        assert_eq!(items[2].expose(), &Value::string("sec"));
+        assert_eq!(items[3].expose(), &Value::string("s"));
+        assert_eq!(items[4].expose().to_string(), "[\"pin\"]");
+    }
+
+    #[test]
+    fn floats_promote_ints() {
+        assert_eq!(eval("return [1 + 2.5, 3 - 0.5, 2 * 1.5, 7.0 / 2, 5.5 % 2, -2.5]"), "[3.5, 2.5, 3.0, 3.5, 1.5, -2.5]");
+        assert_eq!(eval("return [7 / 2, 5 % 3, 1 < 1.5, 2.0 == 2, 2.0]"), "[3, 2, true, true, 2.0]");
+    }
+
+    #[test]
+    fn bools_combine_but_dont_do_arithmetic() {
+        assert_eq!(eval("return [true and false, true or false, !true, 1 == true]"), "[false, true, false, false]");
+        assert_eq!(error("return true + 1").kind, ErrorKind::Type);
+        assert_eq!(error("return true < false").kind, ErrorKind::Type);
+        assert_eq!(error("return 1.0 / 0").kind, ErrorKind::DivisionByZero);
+    }
+
+    #[test]
+    fn functions_get_their_own_frames() {
+        let source = "fn fact(n) {
+    if n <= 1 {
+        return 1
+    }
+    return n * fact(n - 1)
+}
+fn shadow(x) {
+    let n = x * 2
+    return n
+}
+let n = 5
+return [fact(n), shadow(3), n]";
+        assert_eq!(eval(source), "[120, 6, 5]");
+    }
+
+    #[test]
+    fn calls_check_arity_and_depth() {
+        let source = "fn add(a, b) {
+    return a + b
+}
+let g = add
+return g(1)";
+        assert_eq!(error(source).kind, ErrorKind::Arity);
+        assert_eq!(error("let x = 3\nreturn x()").kind, ErrorKind::Type);
+
+        let source = "fn forever(n) {
+    return forever(n + 1)
+}
+try {
+    forever(0)
+} catch e {
+    return e.kind
+}";
+        assert_eq!(eval(source), "StackOverflow");
+    }
+
+    #[test]
+    fn closures_share_captured_variables() {
+        let source = "fn counter() {
+    let count = 0
+    fn next() {
+        count += 1
+        return count
+    }
+    return next
+}
+let a = counter()
+let b = counter()
+a()
+a()
+return [a(), b()]";
+        assert_eq!(eval(source), "[3, 1]");
+    }
+
+    #[test]
+    fn closures_capture_each_iteration_separately() {
+        let source = "let fns = []
+for i in 0..3 {
+    let doubled = i * 2
+    fn get() {
+        return [i, doubled]
+    }
+    fns.push(get)
+}
+for word in [\"a\", \"b\"] {
+    fn say() {
+        return word
+    }
+    fns.push(say)
+}
+let results = []
+for f in fns {
+    results.push(f())
+}
+return results";
+        assert_eq!(eval(source), "[[0, 0], [1, 2], [2, 4], \"a\", \"b\"]");
+    }
+
+    #[test]
+    fn blocks_scope_their_locals() {
+        let source = "let x = 1
+if true {
+    let x = 2
+    x += 1
+}
+return x";
+        assert_eq!(eval(source), "1");
+    }
+
+    #[test]
+    fn loops_iterate_ranges_collections_and_counts() {
+        let source = "let out = []
+for i in 1..4 {
+    out.push(i)
+}
+for key in { \"b\": 1, \"a\": 2 } {
+    out.push(key)
+}
+for c in \"hi\" {
+    out.push(c)
+}
+repeat 2 {
+    out.push(0)
+}
+let n = 3
+while n > 0 {
+    n -= 1
+}
+out.push(n)
+return out";
+        assert_eq!(eval(source), "[1, 2, 3, \"a\", \"b\", \"h\", \"i\", 0, 0, 0]");
+    }
+
+    #[test]
+    fn break_and_continue_affect_the_innermost_loop() {
+        let source = "let out = []
+for i in 0..4 {
+    if i == 1 {
+        continue
+    }
+    for j in 0..10 {
+        if j == 2 {
+            break
+        }
+        out.push(i * 10 + j)
+    }
+    if i == 2 {
+        break
+    }
+}
+let n = 0
+while true {
+    n += 1
+    if n < 5 {
+        continue
+    }
+    break
+}
+return [out, n]";
+        assert_eq!(eval(source), "[[0, 1, 20, 21], 5]");
+    }
+
+    #[test]
+    fn plus_follows_the_coercion_table() {
+        let source = "fn nothing() {
+}
+let a = [1]
+let b = a + [\"x\"]
+return [\"n=\" + 1, 1.5 + \"s\", true + \"!\", \"v: \" + nothing(), \"l\" + [1, \"a\"], { \"k\": 1 } + \"\", 1 + 2.5, a, b]";
+        assert_eq!(
+            eval(source),
+            "[\"n=1\", \"1.5s\", \"true!\", \"v: nil\", \"l[1, \\\"a\\\"]\", \"{\\\"k\\\": 1}\", 3.5, [1], [1, \"x\"]]"
+        );
+    }
+
+    #[test]
+    fn plus_rejects_everything_else() {
+        for source in ["return true + 1", "return {} + {}", "return [1] + 1", "fn f() {\n}\nreturn f + \"x\""] {
+            let error = error(source);
+            assert_eq!(error.kind, ErrorKind::Type, "{}", source);
+            assert!(error.message.contains("+"), "{}", error.message);
+        }
+        let secret = run("secure let pin = \"1234\"\nreturn \"pin: \" + pin").unwrap();
+        assert!(secret.is_secret());
+        assert_eq!(secret.to_string(), "[REDACTED]");
+    }
+
+    #[test]
+    fn runtime_errors_carry_a_line_and_backtrace() {
+        let source = "fn inner(n) {
+    return n / 0
+}
+fn outer(n) {
+    let x = 1
+    return inner(n)
+}
+print outer(4)";
+        let error = error(source);
+        assert_eq!((error.kind, error.line), (ErrorKind::DivisionByZero, 2));
+        let frames: Vec<(&str, usize)> = error.backtrace.iter().map(|frame| (frame.function.as_str(), frame.line)).collect();
+        assert_eq!(frames, [("inner", 2), ("outer", 6), ("<script>", 8)]);
+        assert_eq!(
+            error.to_string(),
+            "DivisionByZero: Division by zero (line 2)\n    at inner (line 2)\n    at outer (line 6)\n    at <script> (line 8)"
+        );
+    }
+
+    #[test]
+    fn catch_binds_errors_and_thrown_values() {
+        let source = "fn fail() {
+    return [1][5]
+}
+let caught = []
+try {
+    fail()
+} catch e {
+    caught.push([e.kind, e.line])
+}
+try {
+    throw { \"code\": 7 }
+} catch e {
+    caught.push(e)
+}
+try {
+    try {
+        throw \"inner\"
+    } catch e {
+        throw e + \" again\"
+    }
+} catch e {
+    caught.push(e)
+}
+return caught";
+        assert_eq!(eval(source), "[[\"IndexError\", 2], {\"code\": 7}, \"inner again\"]");
+    }
+
+    #[test]
+    fn finally_runs_on_return_break_and_errors() {
+        let source = "let log = []
+fn early() {
+    try {
+        return \"returned\"
+    } finally {
+        log.push(\"return\")
+    }
+    return \"not reached\"
+}
+log.push(early())
+for i in 0..3 {
+    try {
+        if i == 1 {
+            break
+        }
+        continue
+    } finally {
+        log.push(i)
+    }
+}
+try {
+    try {
+        throw \"boom\"
+    } finally {
+        log.push(\"error\")
+    }
+} catch e {
+    log.push(e)
+}
+return log";
+        assert_eq!(eval(source), "[\"return\", \"returned\", 0, 1, \"error\", \"boom\"]");
+    }
+
+    #[test]
+    fn returning_out_of_a_try_removes_its_handler() {
+        let source = "fn early() {
+    try {
+        return 1
+    } catch e {
+        return 2
+    }
+}
+early()
+throw \"uncaught\"";
+        assert_eq!(error(source).kind, ErrorKind::Thrown);
+    }
+}